@@ -0,0 +1,728 @@
+use std::{collections::{HashMap, HashSet, VecDeque}, sync::{Arc, Mutex, atomic::{AtomicBool, AtomicUsize, Ordering}}, time::Duration};
+
+use pyo3::prelude::*;
+use pythonize::{pythonize, depythonize};
+use serde_json::{json, Value};
+use futures::future::join_all;
+use tokio::{sync::{Notify, OwnedSemaphorePermit, Semaphore, mpsc::{self, Receiver, Sender}}, time::sleep};
+
+use super::{journal::{Journal, Entry}, CustomUnicomError, Internal};
+
+// upper bound of the retry backoff of durable items
+const RETRY_DELAY_MAX: Duration = Duration::from_secs(300);
+// consecutive failures tolerated by on_failure when max_restarts is not given
+const DEFAULT_MAX_RESTARTS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestartPolicy{
+    // the worker stops on the first exception
+    Never,
+    // the failing item is dropped and the worker goes on, up to max_restarts (5 by default) consecutive failures
+    OnFailure,
+    // same as OnFailure without any limit, max_restarts is ignored
+    Always,
+}
+
+impl TryFrom<&str> for RestartPolicy{
+    type Error = PyErr;
+
+    fn try_from(value: &str) -> Result<Self, Self::Error> {
+        match value{
+            "never" => Ok(RestartPolicy::Never),
+            "on_failure" => Ok(RestartPolicy::OnFailure),
+            "always" => Ok(RestartPolicy::Always),
+            _ => Err(pyo3::exceptions::PyValueError::new_err(format!("unknown restart policy {}, expected never, on_failure or always", value))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Status{
+    Running,
+    Restarting,
+    Stopping,
+    Stopped,
+    Failed,
+}
+
+impl Status{
+    fn as_str(&self) -> &'static str{
+        match self{
+            Status::Running => "running",
+            Status::Restarting => "restarting",
+            Status::Stopping => "stopping",
+            Status::Stopped => "stopped",
+            Status::Failed => "failed",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerSettings{
+    pub queue_size: usize,
+    pub restart: RestartPolicy,
+    pub max_restarts: Option<usize>,
+    pub restart_delay: Duration,
+    pub concurrency: usize,
+    // durable items are retried up to max_attempts times, then moved to the dead letters
+    pub max_attempts: u32,
+    pub retry_delay: Duration,
+}
+
+// python side of a worker, replaced when a reload creates the same durable worker again
+pub struct Handlers{
+    pub callable: PyObject,
+    // called on every item, items with the same key are handled one at a time and in order
+    pub key: Option<PyObject>,
+    pub on_stop: Option<PyObject>,
+}
+
+// counts an item from its creation until a consumer takes it or it is dropped, wherever it waits
+struct Queued(Arc<AtomicUsize>);
+
+impl Queued{
+    fn new(counter: &Arc<AtomicUsize>) -> Queued{
+        counter.fetch_add(1, Ordering::SeqCst);
+        Queued(counter.clone())
+    }
+}
+
+impl Drop for Queued{
+    fn drop(&mut self){
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// journal id and attempt count are only set for durable workers
+pub struct Item{
+    pub object: PyObject,
+    id: Option<u64>,
+    attempts: u32,
+    queued: Option<Queued>,
+}
+
+// keyed workers with several consumers go through a KeyedQueue
+fn keyed(settings: &WorkerSettings, handlers: &Handlers) -> bool{
+    handlers.key.is_some() && settings.concurrency > 1
+}
+
+struct KeyedState<T>{
+    // waiting items of every key, with their slot of the buffer
+    queues: HashMap<u64, VecDeque<(T, OwnedSemaphorePermit)>>,
+    // keys with waiting items and no consumer, oldest first
+    ready: VecDeque<u64>,
+    // keys a consumer is handling
+    busy: HashSet<u64>,
+    waiting: usize,
+    closed: bool,
+    aborted: bool,
+}
+
+// per key queues of a keyed worker: a key is handled by one consumer at a time and any idle
+// consumer takes the next ready key, so a slow key never holds back the others
+struct KeyedQueue<T>{
+    state: Mutex<KeyedState<T>>,
+    slots: Arc<Semaphore>,
+    changed: Notify,
+}
+
+impl<T> KeyedQueue<T>{
+    fn new(size: usize) -> KeyedQueue<T>{
+        KeyedQueue {
+            state: Mutex::new(KeyedState {
+                queues: HashMap::new(),
+                ready: VecDeque::new(),
+                busy: HashSet::new(),
+                waiting: 0,
+                closed: false,
+                aborted: false,
+            }),
+            slots: Arc::new(Semaphore::new(size)),
+            changed: Notify::new(),
+        }
+    }
+
+    // waits until fewer than size items are waiting, None once aborted
+    async fn slot(&self) -> Option<OwnedSemaphorePermit>{
+        self.slots.clone().acquire_owned().await.ok()
+    }
+
+    fn push(&self, key: u64, item: T, slot: OwnedSemaphorePermit){
+        let mut state = self.state.lock().unwrap();
+        state.queues.entry(key).or_default().push_back((item, slot));
+        state.waiting += 1;
+        if !state.busy.contains(&key) && !state.ready.contains(&key){
+            state.ready.push_back(key);
+        }
+        drop(state);
+        self.changed.notify_one();
+    }
+
+    // None once closed and empty, or aborted
+    async fn next(&self) -> Option<(u64, T)>{
+        loop{
+            let changed = self.changed.notified();
+            {
+                let mut state = self.state.lock().unwrap();
+                if state.aborted{
+                    return None
+                }
+                if let Some(key) = state.ready.pop_front(){
+                    let next = state.queues.get_mut(&key).and_then(|queue| queue.pop_front());
+                    if let Some((item, _permit)) = next{
+                        state.waiting -= 1;
+                        state.busy.insert(key);
+                        return Some((key, item))
+                    }
+                    continue
+                }
+                if state.closed && state.waiting == 0{
+                    return None
+                }
+            }
+            changed.await;
+        }
+    }
+
+    // the key goes back to the ready ones if more of its items arrived meanwhile
+    fn done(&self, key: u64){
+        let mut state = self.state.lock().unwrap();
+        state.busy.remove(&key);
+        if state.queues.get(&key).map_or(false, |queue| !queue.is_empty()){
+            state.ready.push_back(key);
+            drop(state);
+            self.changed.notify_one();
+            return
+        }
+        state.queues.remove(&key);
+        if state.closed && state.waiting == 0{
+            drop(state);
+            self.changed.notify_waiters();
+        }
+    }
+
+    fn close(&self){
+        self.state.lock().unwrap().closed = true;
+        self.changed.notify_waiters();
+    }
+
+    // drops the waiting items, durable ones stay in the journal
+    fn abort(&self){
+        {
+            let mut state = self.state.lock().unwrap();
+            state.aborted = true;
+            state.queues.clear();
+            state.ready.clear();
+            state.waiting = 0;
+        }
+        self.slots.close();
+        self.changed.notify_waiters();
+    }
+}
+
+struct WorkerState{
+    status: Status,
+    processed: u64,
+    failed: u64,
+    restarts: u64,
+    last_error: Option<String>,
+}
+
+pub struct BackgroundWorker{
+    name: String,
+    settings: WorkerSettings,
+    journal: Option<Journal>,
+    handlers: Mutex<Handlers>,
+    tx: Mutex<Option<Sender<Item>>>,
+    // items sent and not taken by a consumer yet, in the channel or in a KeyedQueue
+    queued: Arc<AtomicUsize>,
+    state: Mutex<WorkerState>,
+    abort: AtomicBool,
+    failed: AtomicBool,
+    finished: AtomicBool,
+    done: Notify,
+}
+
+impl BackgroundWorker{
+    pub fn new(name: &str, settings: WorkerSettings, journal: Option<Journal>, handlers: Handlers) -> (Arc<BackgroundWorker>, Receiver<Item>){
+        // a keyed worker buffers in its KeyedQueue, the channel only hands items over to the dispatcher
+        let capacity = match keyed(&settings, &handlers){
+            true => 1,
+            false => settings.queue_size.max(1),
+        };
+        let (tx, rx) = mpsc::channel(capacity);
+        let worker = BackgroundWorker {
+            name: name.to_string(),
+            settings,
+            journal,
+            handlers: Mutex::new(handlers),
+            tx: Mutex::new(Some(tx)),
+            queued: Arc::new(AtomicUsize::new(0)),
+            state: Mutex::new(WorkerState { status: Status::Running, processed: 0, failed: 0, restarts: 0, last_error: None }),
+            abort: AtomicBool::new(false),
+            failed: AtomicBool::new(false),
+            finished: AtomicBool::new(false),
+            done: Notify::new(),
+        };
+        (Arc::new(worker), rx)
+    }
+
+    // None once the worker is stopping or stopped
+    pub fn sender(&self) -> Option<Sender<Item>>{
+        self.tx.lock().unwrap().clone()
+    }
+
+    pub fn is_durable(&self) -> bool{
+        self.journal.is_some()
+    }
+
+    pub fn is_finished(&self) -> bool{
+        self.finished.load(Ordering::SeqCst)
+    }
+
+    // a running worker can take the handlers of a reloaded app.py when nothing else changed
+    pub fn accepts(&self, settings: &WorkerSettings, handlers: &Handlers) -> bool{
+        &self.settings == settings && self.handlers.lock().unwrap().key.is_some() == handlers.key.is_some()
+    }
+
+    pub fn replace_handlers(&self, handlers: Handlers){
+        *self.handlers.lock().unwrap() = handlers;
+    }
+
+    fn item(&self, object: PyObject, id: Option<u64>, attempts: u32) -> Item{
+        Item { object, id, attempts, queued: Some(Queued::new(&self.queued)) }
+    }
+
+    // journals the object of a durable worker before it is queued, the item is only acked once handled
+    pub fn prepare(&self, py: Python, object: PyObject) -> PyResult<(Sender<Item>, Item)>{
+        let tx = self.sender().ok_or_else(|| stopped_error(&self.name))?;
+        let id = match &self.journal{
+            Some(journal) => {
+                let value: Value = depythonize(object.as_ref(py))?;
+                // the journal syncs to disk, other python threads go on meanwhile
+                match py.allow_threads(|| journal.add(value)){
+                    Ok(id) => Some(id),
+                    Err(e) => return Err(Internal::new_err(format!("unable to journal item for {} : {}", self.name, e))),
+                }
+            },
+            None => None,
+        };
+        Ok((tx, self.item(object, id, 0)))
+    }
+
+    // an item that never made it to the queue must not be replayed on the next start
+    pub fn rejected(&self, item: &Item){
+        if let (Some(journal), Some(id)) = (&self.journal, item.id){
+            if let Err(e) = journal.ack(id){
+                tracing::error!(worker = %self.name, id, error = %e, "unable to update journal");
+            }
+        }
+    }
+
+    fn journal(&self) -> PyResult<&Journal>{
+        self.journal.as_ref().ok_or_else(|| pyo3::exceptions::PyValueError::new_err(format!("background worker {} is not durable", self.name)))
+    }
+
+    pub fn dead_letters(&self) -> PyResult<Vec<Entry>>{
+        Ok(self.journal()?.dead_letters())
+    }
+
+    pub fn discard(&self, id: u64) -> PyResult<bool>{
+        let journal = self.journal()?;
+        if !journal.dead_letters().iter().any(|entry| entry.id == id){
+            return Ok(false)
+        }
+        journal.ack(id).map_err(|e| Internal::new_err(e.to_string()))?;
+        Ok(true)
+    }
+
+    // the item is queued again with a fresh attempt count, None when id is not a dead letter
+    pub fn requeue(&self, py: Python, id: u64) -> PyResult<Option<(Sender<Item>, Item)>>{
+        let tx = self.sender().ok_or_else(|| stopped_error(&self.name))?;
+        let entry = match self.journal()?.requeue(id).map_err(|e| Internal::new_err(e.to_string()))?{
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        Ok(Some((tx, self.item(pythonize(py, &entry.item)?, Some(entry.id), 0))))
+    }
+
+    fn set_status(&self, status: Status){
+        self.state.lock().unwrap().status = status;
+    }
+
+    // closing the queue lets the worker finish the queued items, without drain they are dropped
+    pub fn stop(&self, drain: bool){
+        if !drain{
+            self.abort.store(true, Ordering::SeqCst);
+        }
+        if self.tx.lock().unwrap().take().is_some(){
+            let mut state = self.state.lock().unwrap();
+            if matches!(state.status, Status::Running | Status::Restarting){
+                state.status = Status::Stopping;
+            }
+        }
+    }
+
+    pub async fn wait(&self){
+        loop{
+            let notified = self.done.notified();
+            if self.finished.load(Ordering::SeqCst){
+                return
+            }
+            notified.await;
+        }
+    }
+
+    pub fn stats(&self) -> Value{
+        let state = self.state.lock().unwrap();
+        json!({
+            "name": self.name,
+            "status": state.status.as_str(),
+            "queue_depth": self.queued.load(Ordering::SeqCst),
+            "queue_size": self.settings.queue_size,
+            "concurrency": self.settings.concurrency,
+            "durable": self.journal.is_some(),
+            "dead_letters": self.journal.as_ref().map_or(0, |journal| journal.dead_letters().len()),
+            "processed": state.processed,
+            "failed": state.failed,
+            "restarts": state.restarts,
+            "last_error": state.last_error,
+        })
+    }
+
+    async fn call(&self, server: &PyObject, item: &PyObject) -> PyResult<()>{
+        Python::with_gil(|py| -> PyResult<_> {
+            let callable = self.handlers.lock().unwrap().callable.clone_ref(py);
+            pyo3_asyncio::tokio::into_future(callable.call1(py, (server.clone_ref(py), item.clone_ref(py),))?.as_ref(py))
+        })?.await?;
+        Ok(())
+    }
+
+    // a failure past the restart policy stops every consumer of the worker
+    fn fail(&self){
+        self.failed.store(true, Ordering::SeqCst);
+        self.abort.store(true, Ordering::SeqCst);
+        self.tx.lock().unwrap().take();
+    }
+
+    fn acked(&self, item: &Item){
+        if let (Some(journal), Some(id)) = (&self.journal, item.id){
+            if let Err(e) = journal.ack(id){
+                tracing::error!(worker = %self.name, id, error = %e, "unable to update journal");
+            }
+        }
+    }
+
+    // schedules another attempt with exponential backoff, the item stays journaled if the worker stops meanwhile
+    fn retry(self: &Arc<Self>, item: Item, error: &str){
+        let (journal, id) = match (&self.journal, item.id){
+            (Some(journal), Some(id)) => (journal, id),
+            _ => return,
+        };
+        let attempts = item.attempts + 1;
+        if attempts >= self.settings.max_attempts{
+            tracing::warn!(worker = %self.name, id, attempts, "item moved to dead letters");
+            if let Err(e) = journal.dead(id, attempts, error){
+                tracing::error!(worker = %self.name, id, error = %e, "unable to update journal");
+            }
+            return
+        }
+        if let Err(e) = journal.retry(id, attempts, error){
+            tracing::error!(worker = %self.name, id, error = %e, "unable to update journal");
+        }
+        let delay = self.settings.retry_delay.saturating_mul(2u32.saturating_pow(attempts - 1)).min(RETRY_DELAY_MAX);
+        let worker = self.clone();
+        tokio::spawn(async move {
+            sleep(delay).await;
+            if let Some(tx) = worker.sender(){
+                let _ = tx.send(worker.item(item.object, Some(id), attempts)).await;
+            }
+        });
+    }
+
+    // replays the items left in the journal by a previous run
+    fn replay(&self){
+        let journal = match &self.journal{
+            Some(journal) => journal,
+            None => return,
+        };
+        let pending = journal.pending();
+        let tx = match self.sender(){
+            Some(tx) if !pending.is_empty() => tx,
+            _ => return,
+        };
+        tracing::info!(worker = %self.name, items = pending.len(), path = %journal.path().display(), "replaying journal");
+        let name = self.name.clone();
+        let queued = self.queued.clone();
+        tokio::spawn(async move {
+            for entry in pending{
+                let object = match Python::with_gil(|py| pythonize(py, &entry.item)){
+                    Ok(object) => object,
+                    Err(e) => {
+                        tracing::error!(worker = %name, id = entry.id, error = %e, "unable to replay journal item");
+                        continue
+                    },
+                };
+                if tx.send(Item { object, id: Some(entry.id), attempts: entry.attempts, queued: Some(Queued::new(&queued)) }).await.is_err(){
+                    break
+                }
+            }
+        });
+    }
+
+    // handles one item, false once the restart policy gives up
+    async fn process(self: &Arc<Self>, mut item: Item, consecutive: &mut usize, server: &PyObject) -> bool{
+        item.queued = None;
+        match self.call(server, &item.object).await{
+            Ok(()) => {
+                *consecutive = 0;
+                self.acked(&item);
+                self.state.lock().unwrap().processed += 1;
+            },
+            Err(e) => {
+                *consecutive += 1;
+                let error: CustomUnicomError = e.into();
+                tracing::error!(worker = %self.name, error = %error.error.description, "background worker item failed");
+                {
+                    let mut state = self.state.lock().unwrap();
+                    state.failed += 1;
+                    state.last_error = Some(error.error.description.clone());
+                }
+                self.retry(item, &error.error.description);
+                let exhausted = match self.settings.restart{
+                    RestartPolicy::Never => true,
+                    RestartPolicy::OnFailure => *consecutive > self.settings.max_restarts.unwrap_or(DEFAULT_MAX_RESTARTS),
+                    RestartPolicy::Always => false,
+                };
+                if exhausted{
+                    tracing::error!(worker = %self.name, "background worker failed, not restarting");
+                    self.fail();
+                    return false
+                }
+                {
+                    let mut state = self.state.lock().unwrap();
+                    state.restarts += 1;
+                    if state.status == Status::Running{
+                        state.status = Status::Restarting;
+                    }
+                }
+                sleep(self.settings.restart_delay).await;
+                let mut state = self.state.lock().unwrap();
+                if state.status == Status::Restarting{
+                    state.status = Status::Running;
+                }
+            },
+        }
+        true
+    }
+
+    async fn consume(self: &Arc<Self>, rx: &tokio::sync::Mutex<Receiver<Item>>, server: &PyObject){
+        let mut consecutive = 0;
+        loop{
+            let item = rx.lock().await.recv().await;
+            let item = match item{
+                Some(item) => item,
+                None => break,
+            };
+            if self.abort.load(Ordering::SeqCst){
+                break
+            }
+            if !self.process(item, &mut consecutive, server).await{
+                break
+            }
+        }
+    }
+
+    async fn consume_keyed(self: &Arc<Self>, queue: &KeyedQueue<Item>, server: &PyObject){
+        let mut consecutive = 0;
+        while let Some((key, item)) = queue.next().await{
+            if self.abort.load(Ordering::SeqCst){
+                queue.abort();
+                break
+            }
+            let carry_on = self.process(item, &mut consecutive, server).await;
+            queue.done(key);
+            if !carry_on{
+                queue.abort();
+                break
+            }
+        }
+    }
+
+    // moves every item to the queue of its key, the slot is taken before receiving so the channel
+    // and the KeyedQueue never hold more than queue_size items together
+    async fn dispatch(&self, rx: &mut Receiver<Item>, queue: &KeyedQueue<Item>){
+        loop{
+            let slot = match queue.slot().await{
+                Some(slot) => slot,
+                None => break,
+            };
+            let item = match rx.recv().await{
+                Some(item) => item,
+                None => break,
+            };
+            if self.abort.load(Ordering::SeqCst){
+                queue.abort();
+                break
+            }
+            let hash = Python::with_gil(|py| -> PyResult<u64> {
+                let key = self.handlers.lock().unwrap().key.as_ref().map(|key| key.clone_ref(py));
+                match key{
+                    Some(key) => Ok(key.call1(py, (item.object.clone_ref(py),))?.as_ref(py).hash()? as u64),
+                    None => Ok(0),
+                }
+            });
+            match hash{
+                Ok(hash) => queue.push(hash, item, slot),
+                Err(e) => {
+                    let error: CustomUnicomError = e.into();
+                    tracing::error!(worker = %self.name, error = %error.error.description, "background worker key failed, item dropped");
+                    if let (Some(journal), Some(id)) = (&self.journal, item.id){
+                        if let Err(e) = journal.dead(id, item.attempts, &error.error.description){
+                            tracing::error!(worker = %self.name, id, error = %e, "unable to update journal");
+                        }
+                    }
+                    let mut state = self.state.lock().unwrap();
+                    state.failed += 1;
+                    state.last_error = Some(error.error.description);
+                },
+            }
+        }
+        queue.close();
+    }
+
+    pub async fn supervise(self: Arc<Self>, mut rx: Receiver<Item>, server: PyObject){
+        self.replay();
+        let concurrency = self.settings.concurrency.max(1);
+        let keyed = keyed(&self.settings, &self.handlers.lock().unwrap());
+        match keyed{
+            true => {
+                // the channel holds the one item the dispatcher waits for
+                let queue = KeyedQueue::new(self.settings.queue_size.saturating_sub(1).max(1));
+                let consumers = join_all((0..concurrency).map(|_| self.consume_keyed(&queue, &server)));
+                futures::join!(self.dispatch(&mut rx, &queue), consumers);
+            },
+            false => {
+                let shared = tokio::sync::Mutex::new(rx);
+                join_all((0..concurrency).map(|_| self.consume(&shared, &server))).await;
+                rx = shared.into_inner();
+            },
+        }
+        let status = match self.failed.load(Ordering::SeqCst){
+            true => Status::Failed,
+            false => Status::Stopped,
+        };
+
+        // senders waiting on a full queue get an error instead of blocking forever
+        self.tx.lock().unwrap().take();
+        rx.close();
+        self.set_status(status);
+
+        let on_stop = Python::with_gil(|py| self.handlers.lock().unwrap().on_stop.as_ref().map(|on_stop| on_stop.clone_ref(py)));
+        if let Some(on_stop) = on_stop{
+            let result = Python::with_gil(|py| -> PyResult<_> {
+                pyo3_asyncio::tokio::into_future(on_stop.call1(py, (server.clone_ref(py),))?.as_ref(py))
+            });
+            if let Err(e) = match result{
+                Ok(future) => future.await.map(|_| ()),
+                Err(e) => Err(e),
+            }{
+                let error: CustomUnicomError = e.into();
+                tracing::error!(worker = %self.name, error = %error.error.description, "background worker on_stop failed");
+            }
+        }
+
+        tracing::info!(worker = %self.name, status = status.as_str(), "background worker stopped");
+        self.finished.store(true, Ordering::SeqCst);
+        self.done.notify_waiters();
+    }
+}
+
+pub fn stopped_error(name: &str) -> PyErr{
+    Internal::new_err(format!("background worker {} is stopped", name))
+}
+
+#[cfg(test)]
+mod tests{
+    use futures::FutureExt;
+
+    use super::KeyedQueue;
+
+    async fn push(queue: &KeyedQueue<&'static str>, key: u64, item: &'static str){
+        let slot = queue.slot().await.unwrap();
+        queue.push(key, item, slot);
+    }
+
+    #[tokio::test]
+    async fn key_order(){
+        let queue = KeyedQueue::new(8);
+        push(&queue, 1, "a1").await;
+        push(&queue, 1, "a2").await;
+        push(&queue, 2, "b1").await;
+        assert_eq!(queue.next().await, Some((1, "a1")));
+        // a2 waits for a1 to be done
+        assert_eq!(queue.next().await, Some((2, "b1")));
+        assert_eq!(queue.next().now_or_never(), None);
+        queue.done(1);
+        assert_eq!(queue.next().await, Some((1, "a2")));
+    }
+
+    #[tokio::test]
+    async fn slow_key(){
+        let queue = KeyedQueue::new(8);
+        push(&queue, 1, "slow").await;
+        assert_eq!(queue.next().await, Some((1, "slow")));
+        for item in ["b1", "b2", "b3"]{
+            push(&queue, 2, item).await;
+        }
+        for item in ["b1", "b2", "b3"]{
+            assert_eq!(queue.next().await, Some((2, item)));
+            queue.done(2);
+        }
+        assert_eq!(queue.next().now_or_never(), None);
+    }
+
+    #[tokio::test]
+    async fn bounded(){
+        let queue = KeyedQueue::new(2);
+        push(&queue, 1, "a").await;
+        push(&queue, 2, "b").await;
+        assert!(queue.slot().now_or_never().is_none());
+        assert_eq!(queue.next().await, Some((1, "a")));
+        assert!(queue.slot().now_or_never().is_some());
+    }
+
+    #[tokio::test]
+    async fn close_drains(){
+        let queue = KeyedQueue::new(8);
+        push(&queue, 1, "a1").await;
+        push(&queue, 1, "a2").await;
+        push(&queue, 2, "b1").await;
+        queue.close();
+        let mut handled = Vec::new();
+        while let Some((key, item)) = queue.next().await{
+            handled.push(item);
+            queue.done(key);
+        }
+        assert_eq!(handled, vec!["a1", "b1", "a2"]);
+
+        // an idle consumer is woken up by close
+        let queue = KeyedQueue::<&'static str>::new(8);
+        let (next, _) = futures::join!(queue.next(), async { queue.close() });
+        assert_eq!(next, None);
+    }
+
+    #[tokio::test]
+    async fn abort(){
+        let queue = KeyedQueue::new(8);
+        push(&queue, 1, "a").await;
+        push(&queue, 2, "b").await;
+        assert_eq!(queue.next().await, Some((1, "a")));
+        queue.abort();
+        assert_eq!(queue.next().await, None);
+        assert!(queue.slot().await.is_none());
+        // a consumer finishing its item after the abort
+        queue.done(1);
+        assert_eq!(queue.next().await, None);
+    }
+}
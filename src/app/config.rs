@@ -1,56 +1,524 @@
-use std::{collections::HashMap, path::Path};
+use std::{collections::HashMap, fmt, path::{Path, PathBuf}, sync::Arc, time::Duration};
 use serde_derive::Deserialize;
+use serde_json::Value;
+use pythonize::pythonize;
+use toml::Spanned;
 use walkdir::WalkDir;
 
 use pyo3::{prelude::*, types::{PyDict, PyList}};
-use unicom_lib::{node::{NodeConfig, api::{Parameter, ApiMethod}, endpoint::{EndPointKind, EndPoint}}, error::UnicomError};
+use unicom_lib::{node::{NodeConfig, api::{Parameter, ApiMethod}, endpoint::{EndPointKind, EndPoint}}, error::{UnicomError, UnicomErrorKind}};
 
-use super::script::PYTHON_SIGNATURE;
+use super::{script::PYTHON_SIGNATURE, parameter::ParameterSpec, template::Templates};
+
+pub const CONFIG_PATH: &str = "config.toml";
+
+#[derive(Debug, Clone)]
+pub struct ConfigIssue{
+    pub line: Option<usize>,
+    pub field: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConfigError{
+    pub file: String,
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl ConfigError{
+    pub fn single(file: &str, line: Option<usize>, field: Option<String>, reason: String) -> ConfigError{
+        ConfigError {
+            file: file.to_string(),
+            issues: vec![ConfigIssue { line, field, reason }],
+        }
+    }
+}
+
+impl fmt::Display for ConfigError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, issue) in self.issues.iter().enumerate(){
+            if index > 0{
+                writeln!(f)?;
+            }
+            write!(f, "{}", self.file)?;
+            if let Some(line) = issue.line{
+                write!(f, ":{}", line)?;
+            }
+            if let Some(field) = &issue.field{
+                write!(f, ": {}", field)?;
+            }
+            write!(f, ": {}", issue.reason)?;
+        }
+        Ok(())
+    }
+}
+
+impl From<ConfigError> for UnicomError{
+    fn from(e: ConfigError) -> Self {
+        UnicomError::new(UnicomErrorKind::InputInvalid, &e.to_string())
+    }
+}
+
+const ENV_PREFIX: &str = "UNICOM_";
+// set in multi mode: every app reads the same environment, so UNICOM_NAME would give all nodes one name
+pub const ENV_MULTI: &str = "UNICOM_MULTI";
+// top level config.toml keys the environment may override, other UNICOM_* variables are ignored
+// UNICOM_APP__* is read as UNICOM_SETTINGS__*
+const ENV_FIELDS: [&str; 10] = [
+    "name", "templates_path", "tags", "endpoints", "shutdown_timeout", "request_timeout",
+    "log", "settings", "workers", "limits",
+];
+
+pub fn profile() -> Option<String>{
+    std::env::var("UNICOM_PROFILE").ok().filter(|p| !p.is_empty())
+}
+
+// Duration::from_secs_f64 panics on nan, inf and anything past u64::MAX seconds
+pub fn seconds(value: f64) -> Option<Duration>{
+    if value.is_finite() && value >= 0.0 && value < u64::MAX as f64{
+        Some(Duration::from_secs_f64(value))
+    }else{
+        None
+    }
+}
+
+fn parse_layer(path: &str, content: &str) -> Result<toml::Value, ConfigError>{
+    match toml::from_str(content){
+        Ok(value) => Ok(value),
+        Err(e) => Err(ConfigError::single(path, e.line_col().map(|(line, _)| line + 1), None, e.to_string())),
+    }
+}
+
+// [app] is the former name of [settings], each layer may use either but not both
+fn alias_app(path: &str, layer: &mut toml::Value) -> Result<(), ConfigError>{
+    let table = match layer.as_table_mut(){
+        Some(table) => table,
+        None => return Ok(()),
+    };
+    if let Some(app) = table.remove("app"){
+        if table.contains_key("settings"){
+            return Err(ConfigError::single(path, None, Some("app".to_string()), "[app] is an alias of [settings], use only one of them".to_string()))
+        }
+        table.insert("settings".to_string(), app);
+    }
+    Ok(())
+}
+
+fn merge(base: &mut toml::Value, layer: toml::Value){
+    match (base, layer){
+        (toml::Value::Table(base), toml::Value::Table(layer)) => {
+            for (key, value) in layer{
+                match base.get_mut(&key){
+                    Some(current) => merge(current, value),
+                    None => {
+                        base.insert(key, value);
+                    },
+                }
+            }
+        },
+        (base, layer) => *base = layer,
+    }
+}
+
+// name, templates_path and tags are always strings, other values are parsed as toml when possible
+fn env_value(fields: &[&str], raw: &str) -> toml::Value{
+    if !matches!(fields.first(), Some(&"name") | Some(&"templates_path") | Some(&"tags")){
+        if let Ok(toml::Value::Table(mut table)) = toml::from_str::<toml::Value>(&format!("value = {}", raw)){
+            if let Some(value) = table.remove("value"){
+                return value
+            }
+        }
+    }
+    toml::Value::String(raw.to_string())
+}
+
+// positions of the config.toml values checked by validate
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct Spans{
+    name: Option<Spanned<toml::Value>>,
+    templates_path: Option<Spanned<toml::Value>>,
+    shutdown_timeout: Option<Spanned<toml::Value>>,
+    request_timeout: Option<Spanned<toml::Value>>,
+    limits: Option<LimitsSpans>,
+    endpoints: Option<Vec<Spanned<toml::Value>>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct LimitsSpans{
+    max_concurrent: Option<Spanned<toml::Value>>,
+    apis: HashMap<String, LimitSpans>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct LimitSpans{
+    max_concurrent: Option<Spanned<toml::Value>>,
+}
+
+// line of a config.toml value, unknown when a profile or the environment overrides it
+fn line_of(content: &str, span: Option<&Spanned<toml::Value>>, current: Option<&toml::Value>) -> Option<usize>{
+    let span = span?;
+    if Some(span.get_ref()) != current{
+        return None
+    }
+    Some(content[..span.start().min(content.len())].matches('\n').count() + 1)
+}
 
 #[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct ConfigModel{
     pub name: String,
     pub templates_path: Option<String>,
     pub tags: Option<HashMap<String, String>>,
     pub endpoints: Option<Vec<EndPoint>>,
+    pub shutdown_timeout: Option<u64>,
+    pub request_timeout: Option<f64>,
+    pub log: Option<LogModel>,
+    // always moved to settings by layered(), only known so that a single file still parses
+    pub app: Option<toml::Value>,
+    pub settings: Option<toml::Value>,
+    pub workers: Option<WorkersModel>,
+    pub limits: Option<LimitsModel>,
+    // directory of the config file, relative paths are resolved from it
+    #[serde(skip)]
+    pub base_dir: PathBuf,
 }
 
-impl ConfigModel {
-    fn new() -> ConfigModel{
-        let content = std::fs::read_to_string("config.toml").unwrap();
-        toml::from_str(&content).unwrap()
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogModel{
+    pub level: Option<String>,
+    pub format: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WorkersModel{
+    pub processes: Option<usize>,
+    pub apis: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitModel{
+    pub max_concurrent: Option<usize>,
+    pub max_queue: Option<usize>,
+}
+
+// [limits] applies to the whole node, [limits.apis."<api>"] or [limits.apis."<api>:<METHOD>"] to one api
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsModel{
+    pub max_concurrent: Option<usize>,
+    pub max_queue: Option<usize>,
+    #[serde(default)]
+    pub apis: HashMap<String, LimitModel>,
+}
+
+#[derive(Debug, Clone)]
+pub struct LimitOptions{
+    pub max_concurrent: usize,
+    pub max_queue: usize,
+}
+
+impl LimitOptions{
+    // the queue defaults to as many waiting requests as running ones
+    fn new(max_concurrent: Option<usize>, max_queue: Option<usize>) -> Option<LimitOptions>{
+        max_concurrent.map(|max_concurrent| LimitOptions {
+            max_concurrent,
+            max_queue: max_queue.unwrap_or(max_concurrent),
+        })
     }
 }
 
-impl TryInto<NodeConfig> for ConfigModel {
-    type Error = UnicomError;
+#[derive(Debug, Clone)]
+pub struct WorkerOptions{
+    pub processes: usize,
+    pub apis: Vec<String>,
+}
 
-    fn try_into(self) -> Result<NodeConfig, Self::Error> {
-        let mut config = NodeConfig::new(&self.name);
-            if self.templates_path.is_some(){
-                for entry in WalkDir::new(self.templates_path.unwrap())
-                        .follow_links(true)
-                        .into_iter()
-                        .filter_map(|e| e.ok()) {
-                    
-                    println!("{:?}", entry);
-                    
-                    if !entry.file_type().is_file(){
-                        continue
+#[derive(Debug, Clone)]
+pub struct NodeOptions{
+    pub shutdown_timeout: Duration,
+    pub request_timeout: Option<Duration>,
+    pub workers: Option<WorkerOptions>,
+    pub limit: Option<LimitOptions>,
+    pub api_limits: HashMap<String, LimitOptions>,
+}
+
+impl NodeOptions{
+    // [limits.apis] keys and [workers] apis can only be checked once app.py has registered its apis
+    pub fn validate_apis(&self, path: &str, api_names: &[String]) -> Result<(), ConfigError>{
+        let methods = ["GET", "POST", "PUT", "DELETE", "PATCH", "HEAD", "OPTIONS"];
+        let mut keys: Vec<&String> = self.api_limits.keys().collect();
+        keys.sort();
+        let mut issues = Vec::new();
+        for key in keys{
+            let (api, method) = match key.split_once(':'){
+                Some((api, method)) => (api, Some(method)),
+                None => (key.as_str(), None),
+            };
+            let reason = if !api_names.iter().any(|name| name == api){
+                format!("unknown api {}, registered apis are: {}", api, api_names.join(", "))
+            }else if let Some(method) = method.filter(|method| !methods.contains(method)){
+                format!("unknown method {}", method)
+            }else{
+                continue
+            };
+            issues.push(ConfigIssue { line: None, field: Some(format!("limits.apis.\"{}\"", key)), reason });
+        }
+        for api in self.workers.iter().flat_map(|workers| workers.apis.iter()){
+            if !api_names.contains(api){
+                issues.push(ConfigIssue {
+                    line: None,
+                    field: Some("workers.apis".to_string()),
+                    reason: format!("unknown api {}, registered apis are: {}", api, api_names.join(", ")),
+                });
+            }
+        }
+        if issues.is_empty(){
+            Ok(())
+        }else{
+            Err(ConfigError { file: path.to_string(), issues })
+        }
+    }
+}
+
+impl From<&ConfigModel> for NodeOptions{
+    fn from(config: &ConfigModel) -> Self {
+        NodeOptions {
+            shutdown_timeout: Duration::from_secs(config.shutdown_timeout.unwrap_or(30)),
+            request_timeout: config.request_timeout.filter(|t| *t > 0.0).and_then(seconds),
+            workers: config.workers.as_ref().filter(|w| !w.apis.is_empty()).map(|w| WorkerOptions {
+                processes: w.processes.unwrap_or(2).max(1),
+                apis: w.apis.clone(),
+            }),
+            limit: config.limits.as_ref().and_then(|l| LimitOptions::new(l.max_concurrent, l.max_queue)),
+            api_limits: config.limits.iter()
+                .flat_map(|l| l.apis.iter())
+                .filter_map(|(name, l)| LimitOptions::new(l.max_concurrent, l.max_queue).map(|l| (name.clone(), l)))
+                .collect(),
+        }
+    }
+}
+
+impl ConfigModel {
+    // defaults < config.toml < config.<profile>.toml < UNICOM_* environment variables,
+    // the (path, content) of each file layer is returned along the merged value
+    fn layered(path: &str, profile: Option<String>, vars: &[(String, String)]) -> Result<(toml::Value, Vec<(String, String)>), ConfigError>{
+        let content = match std::fs::read_to_string(path){
+            Ok(content) => content,
+            Err(e) => return Err(ConfigError::single(path, None, None, format!("unable to read file: {}", e))),
+        };
+        let mut value = parse_layer(path, &content)?;
+        alias_app(path, &mut value)?;
+        let mut files = vec![(path.to_string(), content)];
+
+        if let Some(profile) = profile{
+            let profile_path = Path::new(path).with_file_name(format!("config.{}.toml", profile));
+            let profile_path = profile_path.to_str().unwrap_or_default();
+            match std::fs::read_to_string(profile_path){
+                Ok(profile_content) => {
+                    let mut layer = parse_layer(profile_path, &profile_content)?;
+                    alias_app(profile_path, &mut layer)?;
+                    merge(&mut value, layer);
+                    files.push((profile_path.to_string(), profile_content));
+                },
+                Err(_) => tracing::warn!(profile = %profile, path = profile_path, "no profile config file"),
+            }
+        }
+
+        let multi = vars.iter().any(|(key, _)| key == ENV_MULTI);
+        for (key, raw) in vars{
+            let key = match key.strip_prefix(ENV_PREFIX){
+                Some(key) => key.to_lowercase(),
+                None => continue,
+            };
+            let mut fields: Vec<&str> = key.split("__").collect();
+            if fields[0] == "app"{
+                fields[0] = "settings";
+            }
+            if !ENV_FIELDS.contains(&fields[0]){
+                tracing::debug!(key = %key, "environment variable is not a config override, ignored");
+                continue
+            }
+            if fields[0] == "name" && multi{
+                tracing::warn!(path, "UNICOM_NAME is ignored in multi mode");
+                continue
+            }
+            let mut layer = env_value(&fields, raw);
+            for field in fields.iter().rev(){
+                let mut table = toml::value::Table::new();
+                table.insert(field.to_string(), layer);
+                layer = toml::Value::Table(table);
+            }
+            merge(&mut value, layer);
+        }
+
+        Ok((value, files))
+    }
+
+    pub fn load(path: &str) -> Result<ConfigModel, ConfigError>{
+        let vars: Vec<(String, String)> = std::env::vars().collect();
+        Self::load_with(path, profile(), &vars)
+    }
+
+    // the profile and the environment are passed in so tests do not see the ambient UNICOM_* variables
+    fn load_with(path: &str, profile: Option<String>, vars: &[(String, String)]) -> Result<ConfigModel, ConfigError>{
+        let (value, files) = Self::layered(path, profile, vars)?;
+        let mut model: ConfigModel = match value.clone().try_into(){
+            Ok(model) => model,
+            Err(e) => return Err(Self::layer_error(&files, path, e.to_string())),
+        };
+        let content = &files[0].1;
+        model.base_dir = Path::new(path).parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        model.validate(path, content, &value)?;
+        Ok(model)
+    }
+
+    // the merged value has no positions: each file is parsed again on its own to find the line,
+    // missing fields are expected in a single layer and skipped
+    fn layer_error(files: &[(String, String)], path: &str, reason: String) -> ConfigError{
+        for (file, content) in files{
+            if let Err(e) = toml::from_str::<ConfigModel>(content){
+                if let Some((line, _)) = e.line_col(){
+                    if !e.to_string().starts_with("missing field"){
+                        return ConfigError::single(file, Some(line + 1), None, e.to_string())
                     }
-                    
-                    let mut data :Vec<&str> = entry.path().to_str().unwrap().split("/").collect();
-                    data.remove(0);
+                }
+            }
+        }
+        ConfigError::single(path, None, None, reason)
+    }
 
-                    let terra_path = Path::new(&self.name).join(data.join("/"));
-                    let absolute_path = entry.path().canonicalize().unwrap();
+    fn validate(&self, path: &str, content: &str, value: &toml::Value) -> Result<(), ConfigError>{
+        let mut issues = Vec::new();
+        let spans: Spans = toml::from_str(content).unwrap_or_default();
+        let limits = value.get("limits");
 
-                    println!("{} _ {}", terra_path.to_str().unwrap(), absolute_path.to_str().unwrap());
+        if self.name.trim().is_empty(){
+            issues.push(ConfigIssue { line: line_of(content, spans.name.as_ref(), value.get("name")), field: Some("name".to_string()), reason: "must not be empty".to_string() });
+        }
 
-                    config.add_template(absolute_path.to_str().unwrap(), terra_path.to_str().unwrap());
-                    
+        if let Some(templates_path) = &self.templates_path{
+            if !self.base_dir.join(templates_path).is_dir(){
+                issues.push(ConfigIssue {
+                    line: line_of(content, spans.templates_path.as_ref(), value.get("templates_path")),
+                    field: Some("templates_path".to_string()),
+                    reason: format!("directory {} does not exist", templates_path),
+                });
+            }
+        }
+
+        let timeouts = [
+            ("shutdown_timeout", self.shutdown_timeout.map(|t| t as f64), spans.shutdown_timeout.as_ref()),
+            ("request_timeout", self.request_timeout, spans.request_timeout.as_ref()),
+        ];
+        for (field, timeout, span) in timeouts{
+            let reason = match timeout{
+                Some(timeout) if timeout < 0.0 => "must be positive",
+                Some(timeout) if seconds(timeout).is_none() => "must be a finite number of seconds",
+                _ => continue,
+            };
+            issues.push(ConfigIssue { line: line_of(content, span, value.get(field)), field: Some(field.to_string()), reason: reason.to_string() });
+        }
+
+        if let Some(limits_model) = &self.limits{
+            let limit_spans = spans.limits.unwrap_or_default();
+            let mut concurrency = vec![(
+                "limits.max_concurrent".to_string(),
+                limits_model.max_concurrent,
+                line_of(content, limit_spans.max_concurrent.as_ref(), limits.and_then(|l| l.get("max_concurrent"))),
+            )];
+            for (name, limit) in &limits_model.apis{
+                let span = limit_spans.apis.get(name).and_then(|l| l.max_concurrent.as_ref());
+                let current = limits.and_then(|l| l.get("apis")).and_then(|a| a.get(name)).and_then(|l| l.get("max_concurrent"));
+                concurrency.push((format!("limits.apis.\"{}\".max_concurrent", name), limit.max_concurrent, line_of(content, span, current)));
+            }
+            for (field, max_concurrent, line) in concurrency{
+                if max_concurrent == Some(0){
+                    issues.push(ConfigIssue { line, field: Some(field), reason: "must be greater than 0".to_string() });
+                }
+            }
+        }
+
+        if let Some(endpoints) = &self.endpoints{
+            for (index, endpoint) in endpoints.iter().enumerate(){
+                if let EndPointKind::Static { path } = &endpoint.kind{
+                    if !self.base_dir.join(path).exists(){
+                        let span = spans.endpoints.as_ref().and_then(|e| e.get(index));
+                        let current = value.get("endpoints").and_then(|e| e.get(index));
+                        issues.push(ConfigIssue {
+                            line: line_of(content, span, current),
+                            field: Some(format!("endpoints[{}].kind.path", index)),
+                            reason: format!("static path {} does not exist", path),
+                        });
+                    }
+                }
+            }
+        }
+
+        // endpoints are compared on their raw toml tables, EndPoint has no equality
+        if let Some(endpoints) = value.get("endpoints").and_then(|e| e.as_array()){
+            for (index, endpoint) in endpoints.iter().enumerate(){
+                let route = |e: &toml::Value| e.get("regex").cloned().unwrap_or_else(|| e.clone());
+                if let Some(first) = endpoints[..index].iter().position(|other| route(other) == route(endpoint)){
+                    issues.push(ConfigIssue {
+                        line: None,
+                        field: Some(format!("endpoints[{}]", index)),
+                        reason: format!("duplicate of endpoints[{}]", first),
+                    });
                 }
             }
+        }
+
+        if issues.is_empty(){
+            Ok(())
+        }else{
+            Err(ConfigError { file: path.to_string(), issues })
+        }
+    }
+
+    // (absolute path, template name) of every file under templates_path
+    pub fn templates(&self) -> Vec<(String, String)>{
+        let mut templates = Vec::new();
+        if let Some(templates_path) = &self.templates_path{
+            let root = self.base_dir.join(templates_path);
+            let offset = root.to_str().unwrap().len() - templates_path.len();
+            for entry in WalkDir::new(&root)
+                    .follow_links(true)
+                    .into_iter()
+                    .filter_map(|e| e.ok()) {
+
+                if !entry.file_type().is_file(){
+                    continue
+                }
+                
+                let mut data :Vec<&str> = entry.path().to_str().unwrap()[offset..].split("/").collect();
+                data.remove(0);
+
+                let terra_path = Path::new(&self.name).join(data.join("/"));
+                let absolute_path = entry.path().canonicalize().unwrap();
+
+                tracing::debug!(template = terra_path.to_str().unwrap(), path = absolute_path.to_str().unwrap(), "register template");
+
+                templates.push((absolute_path.to_str().unwrap().to_string(), terra_path.to_str().unwrap().to_string()));
+            }
+        }
+        templates
+    }
+}
+
+impl TryInto<NodeConfig> for ConfigModel {
+    type Error = UnicomError;
+
+    fn try_into(self) -> Result<NodeConfig, Self::Error> {
+        let mut config = NodeConfig::new(&self.name);
+            for (absolute_path, terra_path) in self.templates(){
+                config.add_template(&absolute_path, &terra_path);
+            }
             if self.tags.is_some(){
                 config.tags = self.tags.unwrap();
             }
@@ -59,7 +527,11 @@ impl TryInto<NodeConfig> for ConfigModel {
                     let mut n_endpoint = endpoint.clone();
                     if let Some(endpoint_kind) = match endpoint.kind {
                         EndPointKind::Static { path } => {
-                            Some(EndPointKind::Static { path: Path::new(&path).canonicalize().unwrap().to_str().unwrap().to_string() })
+                            let absolute = match self.base_dir.join(&path).canonicalize(){
+                                Ok(absolute) => absolute,
+                                Err(e) => return Err(UnicomError::new(UnicomErrorKind::InputInvalid, &format!("static path {} : {}", path, e))),
+                            };
+                            Some(EndPointKind::Static { path: absolute.to_str().unwrap().to_string() })
                         },
                         _ => None
                     }{
@@ -78,38 +550,95 @@ impl TryInto<NodeConfig> for ConfigModel {
 pub struct PythonConfig{
     pub config: NodeConfig,
     pub api_objects: Vec<PyObject>,
+    pub api_parameters: Vec<HashMap<String, Vec<ParameterSpec>>>,
+    pub api_names: Vec<String>,
+    pub options: NodeOptions,
+    pub templates: Arc<Templates>,
+    pub name: String,
+    pub profile: Option<String>,
+    // [settings], or its former name [app]
+    pub settings: Value,
+    pub app_path: PathBuf,
 }
 
 
 impl PythonConfig{
-    pub fn new() -> PythonConfig{
-        let config = ConfigModel::new();
-        PythonConfig { 
-            config: config.try_into().unwrap(), 
+    pub fn new(app_path: &str) -> Result<PythonConfig, ConfigError>{
+        let config_path = Path::new(app_path).join(CONFIG_PATH);
+        let config_path = config_path.to_str().unwrap_or(CONFIG_PATH);
+        let config = ConfigModel::load(config_path)?;
+        let options = NodeOptions::from(&config);
+        let templates = Arc::new(Templates::new(config.templates()));
+        let name = config.name.clone();
+        let settings = match &config.settings{
+            Some(settings) => serde_json::to_value(settings).unwrap_or(Value::Null),
+            None => Value::Object(Default::default()),
+        };
+        let config: Result<NodeConfig, UnicomError> = config.try_into();
+        let config = match config{
+            Ok(config) => config,
+            Err(e) => return Err(ConfigError::single(config_path, None, None, e.description)),
+        };
+        Ok(PythonConfig { 
+            config, 
             api_objects: Vec::new(),
-        }
+            api_parameters: Vec::new(),
+            api_names: Vec::new(),
+            options,
+            templates,
+            name,
+            profile: profile(),
+            settings,
+            app_path: PathBuf::from(app_path),
+        })
     }
 }
 
 #[pymethods]
 impl PythonConfig{
 
+    #[getter]
+    fn name(&self) -> String{
+        self.name.clone()
+    }
+
+    #[getter]
+    fn profile(&self) -> Option<String>{
+        self.profile.clone()
+    }
+
+    #[getter]
+    fn tags(&self) -> HashMap<String, String>{
+        self.config.tags.clone()
+    }
+
+    // same table as server.settings, kept for apps written before [settings]
+    #[getter]
+    fn app(&self, py: Python) -> PyResult<PyObject>{
+        Ok(pythonize(py, &self.settings)?)
+    }
+
     pub fn add_api(&mut self, name: String, object: PyObject) -> PyResult<String>{
         let mut methodes = Vec::new();
-        let list_methodes = vec!["GET", "POST", "PUT", "DELETE"];
+        let mut specs = HashMap::new();
+        let list_methodes = vec!["GET", "POST", "PUT", "DELETE", "PATCH", "HEAD", "OPTIONS"];
         Python::with_gil(|py| -> PyResult<()>{
             for s_methode in list_methodes{
                 if let Ok(methode) = object.getattr(py, s_methode){
                     let data = PYTHON_SIGNATURE.call1(py, (methode,))?;
                     let list: &PyList = data.extract(py)?;
                     let mut parameters = Vec::new();
+                    let mut method_specs = Vec::new();
                     for dict in list{
                         let dict: &PyDict = dict.extract().unwrap();
                         let p_name = dict.get_item("name").unwrap().extract()?;
                         let p_kind: &str = dict.get_item("kind").unwrap().extract()?;
                         let p_mandatory = dict.get_item("mandatory").unwrap().extract()?;
+                        let spec_name: &str = dict.get_item("name").unwrap().extract()?;
+                        method_specs.push(ParameterSpec::new(spec_name, p_kind, p_mandatory));
                         parameters.push(Parameter::new(p_name, p_kind.into(), p_mandatory));
                     }
+                    specs.insert(s_methode.to_string(), method_specs);
                     methodes.push(ApiMethod::new(s_methode.into(), parameters))
     
                 }
@@ -119,10 +648,123 @@ impl PythonConfig{
             
         })?;
 
+        if !specs.contains_key("OPTIONS"){
+            methodes.push(ApiMethod::new("OPTIONS".into(), Vec::new()));
+        }
+
         let id = self.api_objects.len() as u64;
         self.config.add_api(id, &name, methodes);
         self.api_objects.push(object);
+        self.api_parameters.push(specs);
+        self.api_names.push(name.clone());
 
         Ok(name)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests{
+    use std::{fs, path::{Path, PathBuf}};
+
+    use super::{ConfigError, ConfigModel, CONFIG_PATH};
+
+    fn app_dir(test: &str, files: &[(&str, &str)]) -> PathBuf{
+        let dir = std::env::temp_dir().join(format!("unicom-config-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for (name, content) in files{
+            fs::write(dir.join(name), content).unwrap();
+        }
+        dir
+    }
+
+    fn load(dir: &Path, profile: Option<&str>, vars: &[(&str, &str)]) -> Result<ConfigModel, ConfigError>{
+        let vars: Vec<(String, String)> = vars.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        ConfigModel::load_with(dir.join(CONFIG_PATH).to_str().unwrap(), profile.map(|p| p.to_string()), &vars)
+    }
+
+    #[test]
+    fn invalid(){
+        let cases = [
+            ("name = \"node\"\nbogus = 1\n", None, Some(2), "bogus"),
+            ("name = \"\"\n", Some("name"), Some(1), "must not be empty"),
+            ("name = \"node\"\ntemplates_path = \"missing\"\n", Some("templates_path"), Some(2), "does not exist"),
+            ("name = \"node\"\nrequest_timeout = inf\n", Some("request_timeout"), Some(2), "finite"),
+            ("name = \"node\"\n[limits]\nmax_concurrent = 0\n", Some("limits.max_concurrent"), Some(3), "greater than 0"),
+            ("name = \"node\"\n[app]\na = 1\n[settings]\nb = 2\n", Some("app"), None, "alias"),
+        ];
+        for (index, (content, field, line, reason)) in cases.into_iter().enumerate(){
+            let dir = app_dir(&format!("invalid-{}", index), &[(CONFIG_PATH, content)]);
+            let error = load(&dir, None, &[]).unwrap_err();
+            assert_eq!(error.file, dir.join(CONFIG_PATH).to_str().unwrap(), "{}", content);
+            assert_eq!(error.issues.len(), 1, "{}", error);
+            assert_eq!(error.issues[0].field.as_deref(), field, "{}", error);
+            assert_eq!(error.issues[0].line, line, "{}", error);
+            assert!(error.issues[0].reason.contains(reason), "{}", error);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn endpoints(){
+        let missing = "name = \"node\"\n\n[[endpoints]]\nregex = \"^/static/(.*)$\"\nkind = { Static = { path = \"missing\" } }\n";
+        let dir = app_dir("static", &[(CONFIG_PATH, missing)]);
+        let error = load(&dir, None, &[]).unwrap_err();
+        assert_eq!(error.issues[0].field.as_deref(), Some("endpoints[0].kind.path"), "{}", error);
+        assert!(error.issues[0].reason.contains("static path missing does not exist"), "{}", error);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let endpoint = "[[endpoints]]\nregex = \"^/static/(.*)$\"\nkind = { Static = { path = \"static\" } }\n";
+        let duplicate = format!("name = \"node\"\n\n{}\n{}", endpoint, endpoint);
+        let dir = app_dir("duplicate", &[(CONFIG_PATH, &duplicate)]);
+        fs::create_dir(dir.join("static")).unwrap();
+        let error = load(&dir, None, &[]).unwrap_err();
+        assert_eq!(error.issues.len(), 1, "{}", error);
+        assert_eq!(error.issues[0].field.as_deref(), Some("endpoints[1]"));
+        assert_eq!(error.issues[0].reason, "duplicate of endpoints[0]");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn profile(){
+        let dir = app_dir("profile", &[
+            (CONFIG_PATH, "name = \"node\"\nshutdown_timeout = 10\n[tags]\nenv = \"dev\"\nzone = \"a\"\n"),
+            ("config.prod.toml", "shutdown_timeout = 5\n[tags]\nenv = \"prod\"\n"),
+        ]);
+        let config = load(&dir, Some("prod"), &[]).unwrap();
+        assert_eq!(config.name, "node");
+        assert_eq!(config.shutdown_timeout, Some(5));
+        let tags = config.tags.unwrap();
+        assert_eq!(tags["env"], "prod");
+        assert_eq!(tags["zone"], "a");
+
+        let config = load(&dir, None, &[]).unwrap();
+        assert_eq!(config.shutdown_timeout, Some(10));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn environment(){
+        let dir = app_dir("environment", &[(CONFIG_PATH, "name = \"node\"\n[tags]\nenv = \"dev\"\n[app]\nkey = 1\n")]);
+        let vars = [
+            ("UNICOM_TAGS__ENV", "prod"),
+            ("UNICOM_REQUEST_TIMEOUT", "2.5"),
+            ("UNICOM_APP__OTHER", "\"x\""),
+            ("UNICOM_SOCKET", "/run/unicom.sock"),
+            ("HOME", "/root"),
+        ];
+        let config = load(&dir, None, &vars).unwrap();
+        assert_eq!(config.tags.unwrap()["env"], "prod");
+        assert_eq!(config.request_timeout, Some(2.5));
+        let settings = config.settings.unwrap();
+        assert_eq!(settings.get("key").and_then(|v| v.as_integer()), Some(1));
+        assert_eq!(settings.get("other").and_then(|v| v.as_str()), Some("x"));
+
+        // every app of multi reads the same environment
+        let config = load(&dir, None, &[("UNICOM_NAME", "other"), ("UNICOM_MULTI", "1")]).unwrap();
+        assert_eq!(config.name, "node");
+        let config = load(&dir, None, &[("UNICOM_NAME", "other")]).unwrap();
+        assert_eq!(config.name, "other");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
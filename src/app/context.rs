@@ -0,0 +1,38 @@
+use pyo3::{prelude::*, types::PyDict};
+
+// passed to handlers declaring a parameter annotated with unicom.RequestContext,
+// or an unannotated `request` or `ctx` parameter
+#[pyclass]
+pub struct RequestContext{
+    #[pyo3(get)]
+    pub id: u64,
+    // node the request was addressed to, unicom requests carry neither the caller nor a deadline
+    #[pyo3(get)]
+    pub target_node: String,
+    #[pyo3(get)]
+    pub api: String,
+    #[pyo3(get)]
+    pub method: String,
+    // scratch space shared by everything handling this request
+    #[pyo3(get)]
+    pub data: Py<PyDict>,
+}
+
+impl RequestContext{
+    pub fn new(py: Python, id: u64, target_node: &str, api: &str, method: &str) -> RequestContext{
+        RequestContext {
+            id,
+            target_node: target_node.to_string(),
+            api: api.to_string(),
+            method: method.to_string(),
+            data: PyDict::new(py).into(),
+        }
+    }
+}
+
+#[pymethods]
+impl RequestContext{
+    fn __repr__(&self) -> String{
+        format!("<RequestContext id={} target_node={} api={} method={}>", self.id, self.target_node, self.api, self.method)
+    }
+}
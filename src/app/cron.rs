@@ -0,0 +1,287 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone};
+
+// how far ahead next() looks before giving up, covers expressions like "0 0 29 2 *"
+const SEARCH_DAYS: i64 = 366 * 8;
+
+// five fields cron expression evaluated in the time zone of the date given to next(): minute hour day-of-month month day-of-week
+#[derive(Debug, Clone)]
+pub struct Cron{
+    minutes: Vec<u32>,
+    hours: Vec<u32>,
+    days: Vec<u32>,
+    months: Vec<u32>,
+    weekdays: Vec<u32>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+// "*", "5", "1-5", "*/15", "1-30/2" and comma separated lists of them
+fn field(spec: &str, name: &str, min: u32, max: u32) -> Result<(Vec<u32>, bool), String>{
+    let mut values = Vec::new();
+    for part in spec.split(','){
+        let (range, step) = match part.split_once('/'){
+            Some((range, step)) => (range, step.parse::<u32>().map_err(|_| format!("invalid step {} in {}", step, name))?),
+            None => (part, 1),
+        };
+        if step == 0{
+            return Err(format!("step must be greater than 0 in {}", name))
+        }
+        let (start, end) = match range{
+            "*" => (min, max),
+            _ => match range.split_once('-'){
+                Some((start, end)) => (
+                    start.parse().map_err(|_| format!("invalid value {} in {}", start, name))?,
+                    end.parse().map_err(|_| format!("invalid value {} in {}", end, name))?,
+                ),
+                None => {
+                    let value = range.parse().map_err(|_| format!("invalid value {} in {}", range, name))?;
+                    (value, if part.contains('/') { max } else { value })
+                },
+            },
+        };
+        if start < min || end > max || start > end{
+            return Err(format!("{} out of range {}-{} in {}", range, min, max, name))
+        }
+        values.extend((start..=end).step_by(step as usize));
+    }
+    values.sort_unstable();
+    values.dedup();
+    Ok((values, spec == "*"))
+}
+
+// february counts 29 days, "0 0 29 2 *" runs on leap years
+fn max_day(month: u32) -> u32{
+    match month{
+        2 => 29,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+impl Cron{
+    pub fn parse(expr: &str) -> Result<Cron, String>{
+        let expr = match expr.trim(){
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            expr => expr,
+        };
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5{
+            return Err(format!("expected 5 fields (minute hour day month weekday), got {}", fields.len()))
+        }
+        let (minutes, _) = field(fields[0], "minute", 0, 59)?;
+        let (hours, _) = field(fields[1], "hour", 0, 23)?;
+        let (days, any_day) = field(fields[2], "day of month", 1, 31)?;
+        let (months, _) = field(fields[3], "month", 1, 12)?;
+        let (mut weekdays, any_weekday) = field(fields[4], "day of week", 0, 7)?;
+        // 7 is an alias of sunday
+        if weekdays.contains(&7){
+            weekdays.retain(|day| *day != 7);
+            if !weekdays.contains(&0){
+                weekdays.insert(0, 0);
+            }
+        }
+        // a restricted day of week always matches some day, a day of month alone may never happen
+        if !any_day && any_weekday && !days.iter().any(|day| months.iter().any(|month| *day <= max_day(*month))){
+            return Err(format!("day of month {} never happens in month {}", fields[2], fields[3]))
+        }
+        Ok(Cron { minutes, hours, days, months, weekdays, any_day, any_weekday })
+    }
+
+    // like cron, a restricted day of month and day of week match when either does
+    fn matches_day(&self, date: NaiveDate) -> bool{
+        if !self.months.contains(&date.month()){
+            return false
+        }
+        let day = self.days.contains(&date.day());
+        let weekday = self.weekdays.contains(&date.weekday().num_days_from_sunday());
+        match (self.any_day, self.any_weekday){
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    // first matching minute strictly after `after`, times skipped by a DST change never match
+    pub fn next<Tz: TimeZone>(&self, after: DateTime<Tz>) -> Option<DateTime<Tz>>{
+        let start = after.date().naive_local();
+        for offset in 0..SEARCH_DAYS{
+            let date = start + Duration::days(offset);
+            if !self.matches_day(date){
+                continue
+            }
+            for hour in &self.hours{
+                for minute in &self.minutes{
+                    let time = match date.and_hms_opt(*hour, *minute, 0){
+                        Some(time) => time,
+                        None => continue,
+                    };
+                    if let Some(time) = after.timezone().from_local_datetime(&time).earliest(){
+                        if time > after{
+                            return Some(time)
+                        }
+                    }
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use chrono::{DateTime, Duration, FixedOffset, LocalResult, NaiveDate, NaiveDateTime, Offset, TimeZone, Utc};
+
+    use super::{field, Cron};
+
+    fn utc(y: i32, m: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc>{
+        Utc.ymd(y, m, d).and_hms(h, mi, 0)
+    }
+
+    #[test]
+    fn fields(){
+        let cases = [
+            ("*", 0, 5, Some(vec![0, 1, 2, 3, 4, 5])),
+            ("*/15", 0, 59, Some(vec![0, 15, 30, 45])),
+            ("5/20", 0, 59, Some(vec![5, 25, 45])),
+            ("1-10/3", 0, 59, Some(vec![1, 4, 7, 10])),
+            ("1,3-4,3", 0, 59, Some(vec![1, 3, 4])),
+            ("*/0", 0, 59, None),
+            ("60", 0, 59, None),
+            ("5-1", 0, 59, None),
+            ("a", 0, 59, None),
+            ("0", 1, 31, None),
+        ];
+        for (spec, min, max, expected) in cases{
+            assert_eq!(field(spec, "test", min, max).ok().map(|(values, _)| values), expected, "{}", spec);
+        }
+    }
+
+    #[test]
+    fn parse(){
+        let cases = [
+            ("* * * * *", true),
+            ("@daily", true),
+            ("0 0 29 2 *", true),
+            ("0 0 30 2 1", true),
+            ("0 0 * *", false),
+            ("0 0 30 2 *", false),
+            ("0 0 31 4,6,9,11 *", false),
+            ("0 0 31 4,5 *", true),
+            ("0 24 * * *", false),
+            ("0 0 * * 8", false),
+        ];
+        for (expr, valid) in cases{
+            assert_eq!(Cron::parse(expr).is_ok(), valid, "{}", expr);
+        }
+    }
+
+    #[test]
+    fn next(){
+        let cases = [
+            // steps and ranges
+            ("*/15 * * * *", utc(2021, 3, 1, 10, 7), utc(2021, 3, 1, 10, 15)),
+            ("*/15 * * * *", utc(2021, 3, 1, 10, 45), utc(2021, 3, 1, 11, 0)),
+            ("0 9-17/4 * * *", utc(2021, 3, 1, 13, 0), utc(2021, 3, 1, 17, 0)),
+            ("0 0 1 * *", utc(2021, 12, 15, 0, 0), utc(2022, 1, 1, 0, 0)),
+            // 2021-03-01 is a monday, 7 and 0 are both sunday
+            ("0 0 * * 7", utc(2021, 3, 1, 0, 0), utc(2021, 3, 7, 0, 0)),
+            ("0 0 * * 0", utc(2021, 3, 1, 0, 0), utc(2021, 3, 7, 0, 0)),
+            ("0 0 * * 6-7", utc(2021, 3, 1, 0, 0), utc(2021, 3, 6, 0, 0)),
+            // day of month or day of week: fridays of august 2021 are the 6th, 13th and 20th
+            ("0 0 13 * 5", utc(2021, 8, 1, 0, 0), utc(2021, 8, 6, 0, 0)),
+            ("0 0 13 * 5", utc(2021, 8, 6, 0, 0), utc(2021, 8, 13, 0, 0)),
+            ("0 0 13 * 5", utc(2021, 8, 13, 0, 0), utc(2021, 8, 20, 0, 0)),
+            ("0 0 1 * 5", utc(2021, 8, 27, 0, 0), utc(2021, 9, 1, 0, 0)),
+            // a step in the day field restricts it
+            ("0 0 */10 * *", utc(2021, 4, 21, 0, 0), utc(2021, 5, 1, 0, 0)),
+            ("0 0 */10 * *", utc(2021, 5, 21, 0, 0), utc(2021, 5, 31, 0, 0)),
+            ("0 0 */10 * 1", utc(2021, 3, 1, 0, 0), utc(2021, 3, 8, 0, 0)),
+            // leap day
+            ("0 0 29 2 *", utc(2021, 3, 1, 0, 0), utc(2024, 2, 29, 0, 0)),
+        ];
+        for (expr, after, expected) in cases{
+            assert_eq!(Cron::parse(expr).unwrap().next(after), Some(expected), "{} after {}", expr, after);
+        }
+    }
+
+    // central european time of 2021: 02:00-03:00 does not exist on march 28th
+    // and happens twice on october 31st
+    #[derive(Debug, Clone)]
+    struct Paris;
+
+    fn transitions() -> (NaiveDateTime, NaiveDateTime){
+        (NaiveDate::from_ymd(2021, 3, 28).and_hms(2, 0, 0), NaiveDate::from_ymd(2021, 10, 31).and_hms(2, 0, 0))
+    }
+
+    impl TimeZone for Paris{
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Paris{
+            Paris
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> LocalResult<FixedOffset>{
+            self.offset_from_local_datetime(&local.and_hms(12, 0, 0))
+        }
+
+        fn offset_from_local_datetime(&self, local: &NaiveDateTime) -> LocalResult<FixedOffset>{
+            let (winter, summer) = (FixedOffset::east(3600), FixedOffset::east(7200));
+            let (spring, autumn) = transitions();
+            if *local >= spring && *local < spring + Duration::hours(1){
+                LocalResult::None
+            }else if *local >= autumn && *local < autumn + Duration::hours(1){
+                LocalResult::Ambiguous(summer, winter)
+            }else if *local >= spring && *local < autumn{
+                LocalResult::Single(summer)
+            }else{
+                LocalResult::Single(winter)
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset{
+            self.offset_from_utc_datetime(&utc.and_hms(12, 0, 0))
+        }
+
+        // both changes happen at 01:00 utc
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset{
+            let (spring, autumn) = transitions();
+            if *utc >= spring - Duration::hours(1) && *utc < autumn - Duration::hours(1){
+                FixedOffset::east(7200)
+            }else{
+                FixedOffset::east(3600)
+            }
+        }
+    }
+
+    fn paris(y: i32, m: u32, d: u32, h: u32, mi: u32) -> DateTime<Paris>{
+        Paris.from_local_datetime(&NaiveDate::from_ymd(y, m, d).and_hms(h, mi, 0)).earliest().unwrap()
+    }
+
+    fn local(time: Option<DateTime<Paris>>) -> Option<(NaiveDateTime, i32)>{
+        time.map(|time| (time.naive_local(), time.offset().fix().local_minus_utc()))
+    }
+
+    #[test]
+    fn dst_gap(){
+        let cron = Cron::parse("30 2 * * *").unwrap();
+        // 02:30 does not exist on the 28th, that run is skipped
+        assert_eq!(local(cron.next(paris(2021, 3, 27, 12, 0))), Some((NaiveDate::from_ymd(2021, 3, 29).and_hms(2, 30, 0), 7200)));
+        // a time right after the gap still runs
+        let cron = Cron::parse("0 3 * * *").unwrap();
+        assert_eq!(local(cron.next(paris(2021, 3, 27, 12, 0))), Some((NaiveDate::from_ymd(2021, 3, 28).and_hms(3, 0, 0), 7200)));
+    }
+
+    #[test]
+    fn dst_overlap(){
+        let cron = Cron::parse("30 2 * * *").unwrap();
+        // 02:30 happens twice on the 31st, only the first one runs
+        let first = cron.next(paris(2021, 10, 30, 12, 0));
+        assert_eq!(local(first.clone()), Some((NaiveDate::from_ymd(2021, 10, 31).and_hms(2, 30, 0), 7200)));
+        assert_eq!(local(cron.next(first.unwrap())), Some((NaiveDate::from_ymd(2021, 11, 1).and_hms(2, 30, 0), 3600)));
+    }
+}
@@ -0,0 +1,302 @@
+use std::{collections::BTreeMap, fs::{self, File, OpenOptions}, io::{self, BufRead, BufReader, Write}, path::{Path, PathBuf}, sync::Mutex};
+
+use serde_derive::{Serialize, Deserialize};
+use serde_json::Value;
+
+// directory of the queue journals, relative to the app directory
+pub const JOURNAL_DIR: &str = ".unicom/queues";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry{
+    pub id: u64,
+    pub item: Value,
+    pub attempts: u32,
+    pub error: Option<String>,
+}
+
+// one json record per line, replayed in order on open
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum Record{
+    Add{ id: u64, item: Value },
+    Ack{ id: u64 },
+    Retry{ id: u64, attempts: u32, error: String },
+    Dead{ id: u64, attempts: u32, error: String },
+    Requeue{ id: u64 },
+}
+
+struct JournalState{
+    file: File,
+    next_id: u64,
+    pending: BTreeMap<u64, Entry>,
+    dead: BTreeMap<u64, Entry>,
+}
+
+impl JournalState{
+    fn apply(&mut self, record: Record){
+        match record{
+            Record::Add { id, item } => {
+                self.next_id = self.next_id.max(id + 1);
+                self.pending.insert(id, Entry { id, item, attempts: 0, error: None });
+            },
+            Record::Ack { id } => {
+                self.pending.remove(&id);
+                self.dead.remove(&id);
+            },
+            Record::Retry { id, attempts, error } => {
+                if let Some(entry) = self.pending.get_mut(&id){
+                    entry.attempts = attempts;
+                    entry.error = Some(error);
+                }
+            },
+            Record::Dead { id, attempts, error } => {
+                if let Some(mut entry) = self.pending.remove(&id){
+                    entry.attempts = attempts;
+                    entry.error = Some(error);
+                    self.dead.insert(id, entry);
+                }
+            },
+            Record::Requeue { id } => {
+                if let Some(mut entry) = self.dead.remove(&id){
+                    entry.attempts = 0;
+                    entry.error = None;
+                    self.pending.insert(id, entry);
+                }
+            },
+        }
+    }
+
+    fn write(&mut self, record: &Record) -> io::Result<()>{
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()
+    }
+}
+
+// the worker name becomes a file name under JOURNAL_DIR and must not leave it
+pub fn valid_name(name: &str) -> bool{
+    !name.is_empty() && !name.starts_with('.') && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+// append-only file backing a durable background worker queue
+pub struct Journal{
+    path: PathBuf,
+    state: Mutex<JournalState>,
+}
+
+impl Journal{
+    // replays the journal then rewrites it with only the live entries
+    pub fn open(app_path: &Path, name: &str) -> io::Result<Journal>{
+        if !valid_name(name){
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("invalid journal name {:?}", name)))
+        }
+        let dir = app_path.join(JOURNAL_DIR);
+        fs::create_dir_all(&dir)?;
+        let path = dir.join(format!("{}.jsonl", name));
+
+        let mut records = Vec::new();
+        if path.exists(){
+            for (index, line) in BufReader::new(File::open(&path)?).lines().enumerate(){
+                let line = line?;
+                if line.trim().is_empty(){
+                    continue
+                }
+                match serde_json::from_str::<Record>(&line){
+                    Ok(record) => records.push(record),
+                    // a torn last line after a crash is expected, anything else is reported
+                    Err(e) => tracing::warn!(path = %path.display(), line = index + 1, error = %e, "skipping invalid journal record"),
+                }
+            }
+        }
+
+        let compact = path.with_extension("jsonl.tmp");
+        let mut state = JournalState {
+            file: File::create(&compact)?,
+            next_id: 1,
+            pending: BTreeMap::new(),
+            dead: BTreeMap::new(),
+        };
+        for record in records{
+            state.apply(record);
+        }
+        let entries: Vec<(Entry, bool)> = state.pending.values().map(|e| (e.clone(), false))
+            .chain(state.dead.values().map(|e| (e.clone(), true)))
+            .collect();
+        for (entry, dead) in entries{
+            state.write(&Record::Add { id: entry.id, item: entry.item })?;
+            if let Some(error) = entry.error{
+                match dead{
+                    true => state.write(&Record::Dead { id: entry.id, attempts: entry.attempts, error })?,
+                    false => state.write(&Record::Retry { id: entry.id, attempts: entry.attempts, error })?,
+                }
+            }
+        }
+        fs::rename(&compact, &path)?;
+        state.file = OpenOptions::new().append(true).open(&path)?;
+
+        Ok(Journal { path, state: Mutex::new(state) })
+    }
+
+    fn record(&self, record: Record) -> io::Result<()>{
+        let mut state = self.state.lock().unwrap();
+        state.write(&record)?;
+        state.apply(record);
+        Ok(())
+    }
+
+    pub fn add(&self, item: Value) -> io::Result<u64>{
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_id;
+        let record = Record::Add { id, item };
+        state.write(&record)?;
+        state.apply(record);
+        Ok(id)
+    }
+
+    pub fn ack(&self, id: u64) -> io::Result<()>{
+        self.record(Record::Ack { id })
+    }
+
+    pub fn retry(&self, id: u64, attempts: u32, error: &str) -> io::Result<()>{
+        self.record(Record::Retry { id, attempts, error: error.to_string() })
+    }
+
+    pub fn dead(&self, id: u64, attempts: u32, error: &str) -> io::Result<()>{
+        self.record(Record::Dead { id, attempts, error: error.to_string() })
+    }
+
+    // moves a dead letter back to the queue with a fresh attempt count
+    pub fn requeue(&self, id: u64) -> io::Result<Option<Entry>>{
+        if !self.state.lock().unwrap().dead.contains_key(&id){
+            return Ok(None)
+        }
+        self.record(Record::Requeue { id })?;
+        Ok(self.state.lock().unwrap().pending.get(&id).cloned())
+    }
+
+    pub fn pending(&self) -> Vec<Entry>{
+        self.state.lock().unwrap().pending.values().cloned().collect()
+    }
+
+    pub fn dead_letters(&self) -> Vec<Entry>{
+        self.state.lock().unwrap().dead.values().cloned().collect()
+    }
+
+    pub fn path(&self) -> &Path{
+        &self.path
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::{fs, io::Write, path::PathBuf};
+
+    use serde_json::json;
+
+    use super::{valid_name, Journal, JOURNAL_DIR};
+
+    fn app_dir(test: &str) -> PathBuf{
+        let dir = std::env::temp_dir().join(format!("unicom-journal-{}-{}", test, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn ids(entries: Vec<super::Entry>) -> Vec<u64>{
+        entries.iter().map(|entry| entry.id).collect()
+    }
+
+    #[test]
+    fn replay(){
+        let dir = app_dir("replay");
+        {
+            let journal = Journal::open(&dir, "jobs").unwrap();
+            assert_eq!(journal.add(json!("a")).unwrap(), 1);
+            assert_eq!(journal.add(json!("b")).unwrap(), 2);
+            assert_eq!(journal.add(json!({"c": 3})).unwrap(), 3);
+            journal.ack(1).unwrap();
+            journal.retry(2, 1, "boom").unwrap();
+            journal.dead(3, 5, "failed").unwrap();
+        }
+        let journal = Journal::open(&dir, "jobs").unwrap();
+        let pending = journal.pending();
+        assert_eq!(ids(pending.clone()), vec![2]);
+        assert_eq!(pending[0].item, json!("b"));
+        assert_eq!(pending[0].attempts, 1);
+        assert_eq!(pending[0].error.as_deref(), Some("boom"));
+        let dead = journal.dead_letters();
+        assert_eq!(ids(dead.clone()), vec![3]);
+        assert_eq!(dead[0].item, json!({"c": 3}));
+        assert_eq!(dead[0].attempts, 5);
+        // ids keep growing after a replay
+        assert_eq!(journal.add(json!("d")).unwrap(), 4);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn compaction(){
+        let dir = app_dir("compaction");
+        {
+            let journal = Journal::open(&dir, "jobs").unwrap();
+            for index in 0..10{
+                let id = journal.add(json!(index)).unwrap();
+                if index != 4{
+                    journal.ack(id).unwrap();
+                }
+            }
+        }
+        let journal = Journal::open(&dir, "jobs").unwrap();
+        let content = fs::read_to_string(journal.path()).unwrap();
+        assert_eq!(content.lines().count(), 1);
+        assert_eq!(ids(journal.pending()), vec![5]);
+        assert!(!journal.path().with_extension("jsonl.tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn torn_line(){
+        let dir = app_dir("torn");
+        {
+            let journal = Journal::open(&dir, "jobs").unwrap();
+            journal.add(json!("a")).unwrap();
+        }
+        let path = dir.join(JOURNAL_DIR).join("jobs.jsonl");
+        fs::OpenOptions::new().append(true).open(&path).unwrap().write_all(b"{\"op\":\"add\",\"id\":2,\"it").unwrap();
+        let journal = Journal::open(&dir, "jobs").unwrap();
+        assert_eq!(ids(journal.pending()), vec![1]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn requeue(){
+        let dir = app_dir("requeue");
+        let journal = Journal::open(&dir, "jobs").unwrap();
+        let id = journal.add(json!("a")).unwrap();
+        assert!(journal.requeue(id).unwrap().is_none());
+        journal.dead(id, 3, "failed").unwrap();
+        let entry = journal.requeue(id).unwrap().unwrap();
+        assert_eq!((entry.id, entry.attempts, entry.error), (id, 0, None));
+        assert!(journal.dead_letters().is_empty());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn names(){
+        let cases = [
+            ("jobs", true),
+            ("mail-out_2.v1", true),
+            ("", false),
+            (".hidden", false),
+            ("..", false),
+            ("../jobs", false),
+            ("a/b", false),
+            ("a\\b", false),
+            ("é", false),
+        ];
+        for (name, expected) in cases{
+            assert_eq!(valid_name(name), expected, "{}", name);
+        }
+        assert!(Journal::open(&std::env::temp_dir(), "../escape").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::{collections::HashMap, sync::{Arc, atomic::{AtomicU64, AtomicUsize, Ordering}}};
+
+use serde_json::{json, Map, Value};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use unicom_lib::error::{UnicomError, UnicomErrorKind};
+
+use super::config::{LimitOptions, NodeOptions};
+
+struct Limit{
+    name: String,
+    semaphore: Arc<Semaphore>,
+    max_concurrent: usize,
+    max_queue: usize,
+    queued: AtomicUsize,
+    rejected: AtomicU64,
+}
+
+// counts a request waiting for a slot, also when its future is dropped while queued
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_>{
+    fn drop(&mut self){
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Limit{
+    fn new(name: &str, options: &LimitOptions) -> Limit{
+        Limit {
+            name: name.to_string(),
+            semaphore: Arc::new(Semaphore::new(options.max_concurrent)),
+            max_concurrent: options.max_concurrent,
+            max_queue: options.max_queue,
+            queued: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    // rejects right away once max_queue requests are already waiting for a slot
+    async fn acquire(&self) -> Result<OwnedSemaphorePermit, UnicomError>{
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned(){
+            return Ok(permit)
+        }
+        let waiting = self.queued.fetch_add(1, Ordering::SeqCst);
+        let _queued = Queued(&self.queued);
+        if waiting >= self.max_queue{
+            self.rejected.fetch_add(1, Ordering::SeqCst);
+            tracing::warn!(limit = %self.name, max_concurrent = self.max_concurrent, max_queue = self.max_queue, "request rejected, queue full");
+            return Err(UnicomError::new(UnicomErrorKind::NotAllowed, &format!("too many requests for {} ({} running, {} queued)", self.name, self.max_concurrent, self.max_queue)))
+        }
+        tracing::debug!(limit = %self.name, queued = self.queued.load(Ordering::SeqCst), "request queued");
+        let permit = self.semaphore.clone().acquire_owned().await;
+        permit.map_err(|_| UnicomError::new(UnicomErrorKind::Internal, "limiter closed"))
+    }
+
+    fn stats(&self) -> Value{
+        json!({
+            "max_concurrent": self.max_concurrent,
+            "max_queue": self.max_queue,
+            "running": self.max_concurrent - self.semaphore.available_permits(),
+            "queued": self.queued.load(Ordering::SeqCst),
+            "rejected": self.rejected.load(Ordering::SeqCst),
+        })
+    }
+}
+
+// held for the lifetime of a request, releases its api and node slots on drop
+pub struct LimitPermit{
+    _permits: Vec<OwnedSemaphorePermit>,
+}
+
+pub struct Limiter{
+    node: Option<Limit>,
+    apis: HashMap<String, Limit>,
+}
+
+impl Limiter{
+    pub fn new(options: &NodeOptions) -> Limiter{
+        Limiter {
+            node: options.limit.as_ref().map(|options| Limit::new("node", options)),
+            apis: options.api_limits.iter().map(|(name, options)| (name.clone(), Limit::new(name, options))).collect(),
+        }
+    }
+
+    // the api slot is taken before the node one so a saturated api does not hold node slots while waiting
+    pub async fn acquire(&self, api: &str, method: &str) -> Result<LimitPermit, UnicomError>{
+        let mut permits = Vec::new();
+        if let Some(limit) = self.apis.get(&format!("{}:{}", api, method)){
+            permits.push(limit.acquire().await?);
+        }
+        if let Some(limit) = self.apis.get(api){
+            permits.push(limit.acquire().await?);
+        }
+        if let Some(limit) = &self.node{
+            permits.push(limit.acquire().await?);
+        }
+        Ok(LimitPermit { _permits: permits })
+    }
+
+    pub fn stats(&self) -> Value{
+        let mut apis = Map::new();
+        for (name, limit) in &self.apis{
+            apis.insert(name.clone(), limit.stats());
+        }
+        json!({
+            "node": self.node.as_ref().map(|limit| limit.stats()),
+            "apis": apis,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests{
+    use std::sync::atomic::Ordering;
+
+    use futures::FutureExt;
+
+    use super::{Limit, LimitOptions};
+
+    fn limit(max_concurrent: usize, max_queue: usize) -> Limit{
+        Limit::new("test", &LimitOptions { max_concurrent, max_queue })
+    }
+
+    #[tokio::test]
+    async fn acquire(){
+        let limit = limit(2, 1);
+        let first = limit.acquire().await.unwrap();
+        let _second = limit.acquire().await.unwrap();
+        assert_eq!(limit.queued.load(Ordering::SeqCst), 0);
+
+        let mut third = Box::pin(limit.acquire());
+        assert!((&mut third).now_or_never().is_none());
+        assert_eq!(limit.queued.load(Ordering::SeqCst), 1);
+
+        assert!(limit.acquire().await.is_err());
+        assert_eq!(limit.rejected.load(Ordering::SeqCst), 1);
+        assert_eq!(limit.queued.load(Ordering::SeqCst), 1);
+
+        drop(first);
+        assert!(third.await.is_ok());
+        assert_eq!(limit.queued.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn dropped_request(){
+        let limit = limit(1, 1);
+        let running = limit.acquire().await.unwrap();
+        let mut queued = Box::pin(limit.acquire());
+        assert!((&mut queued).now_or_never().is_none());
+        drop(queued);
+        assert_eq!(limit.queued.load(Ordering::SeqCst), 0);
+
+        // the queue slot of the dropped request is free again
+        let mut next = Box::pin(limit.acquire());
+        assert!((&mut next).now_or_never().is_none());
+        drop(running);
+        assert!(next.await.is_ok());
+        assert_eq!(limit.rejected.load(Ordering::SeqCst), 0);
+    }
+}
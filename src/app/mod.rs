@@ -1,17 +1,32 @@
-use std::{path::Path, sync::Arc};
+use std::{collections::{HashMap, HashSet}, path::Path, sync::{Arc, RwLock, atomic::{AtomicBool, AtomicUsize, Ordering}}, time::{Duration, SystemTime}};
 use pyo3::types::PyBytes;
 use pyo3::{prelude::*, types::PyList, create_exception, exceptions::PyException};
 use pyo3::{PyErr, PyErrArguments};
 use serde_json::Value;
-use tokio::{fs, sync::{Mutex, mpsc::{self,  Receiver, Sender}}};
+use tokio::{fs, sync::{Mutex, mpsc::{self,  Receiver, Sender}}, time::{sleep, timeout}};
+use walkdir::WalkDir;
+use futures::future::join_all;
 use unicom_lib::error::UnicomErrorKind;
 use unicom_lib::{node::{message::request::UnicomRequest, utils::pending::PendingController, NodeConfig}, error::UnicomError};
 use pythonize::{pythonize, depythonize};
-use self::{server::PythonServer, config::PythonConfig, script::PYTHON_EXECUTE};
+use unicom_lib::node::endpoint::EndPoint;
+use self::{server::PythonServer, config::{PythonConfig, NodeOptions, ConfigError, CONFIG_PATH}, tracker::{Tracker, TrackerGuard}, response::Response, template::Template, context::RequestContext, script::{PYTHON_EXECUTE, PYTHON_PURGE_MODULES}, parameter::{ParameterSpec, validate, describe}, worker::WorkerPool, limits::Limiter};
 
 pub mod script;
 mod server;
-mod config;
+pub mod config;
+mod parameter;
+mod module;
+mod tracker;
+mod response;
+mod template;
+pub mod worker;
+mod limits;
+mod context;
+mod background;
+mod journal;
+mod cron;
+mod scheduler;
 
 create_exception!(unicom, UnicomPyError, PyException);
 
@@ -22,6 +37,7 @@ create_exception!(unicom, Internal, PyException);
 create_exception!(unicom, NotAllowed, PyException);
 create_exception!(unicom, MethodNotAllowed, PyException);
 create_exception!(unicom, Empty, PyException);
+create_exception!(unicom, Timeout, PyException);
 
 
 #[derive(Debug)]
@@ -30,20 +46,198 @@ pub enum PythonMessage{
         id: u64,
         data: UnicomRequest
     },
+    Cancel{
+        id: u64
+    },
     Quit
 }
 
-enum PythonReturn{
+pub enum PythonReturn{
     Binary(Vec<u8>),
     Value(Value),
+    Error(UnicomError),
 }
 
-pub struct App{
+// failures of AppModule::load, a [settings] rejected by settings_schema is reported as a config error
+enum LoadError{
+    Config(ConfigError),
+    Python(PyErr),
+}
+
+impl From<PyErr> for LoadError{
+    fn from(e: PyErr) -> Self {
+        LoadError::Python(e)
+    }
+}
+
+impl From<LoadError> for PyErr{
+    fn from(e: LoadError) -> Self {
+        match e{
+            LoadError::Config(e) => InputInvalid::new_err(e.to_string()),
+            LoadError::Python(e) => e,
+        }
+    }
+}
+
+struct AppModule{
     api_objects: Vec<PyObject>,
+    api_parameters: Vec<HashMap<String, Vec<ParameterSpec>>>,
+    api_names: Vec<String>,
     run_object: Option<PyObject>,
     close_object: Option<PyObject>,
+}
+
+// apps share sys.path and sys.modules in multi mode, the first app importing a module name would
+// shadow the module of the same name in the others
+pub fn shared_modules(app_paths: &[String]) -> Vec<String>{
+    let mut owners: HashMap<String, &str> = HashMap::new();
+    let mut shared = Vec::new();
+    for app_path in app_paths{
+        let entries = match std::fs::read_dir(app_path){
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let path = entry.path();
+                let name = path.file_stem()?.to_str()?.to_string();
+                if name.starts_with('.') || name == "__pycache__" || name == "app"{
+                    return None
+                }
+                let package = path.is_dir() && std::fs::read_dir(&path).ok()?
+                    .filter_map(|e| e.ok())
+                    .any(|e| e.path().extension().map_or(false, |ext| ext == "py"));
+                let module = path.is_file() && path.extension().map_or(false, |ext| ext == "py");
+                if package || module{
+                    Some(name)
+                }else{
+                    None
+                }
+            })
+            .collect();
+        names.sort();
+        names.dedup();
+        for name in names{
+            match owners.get(&name){
+                Some(owner) => shared.push(format!("module {} is defined by {} and {}", name, owner, app_path)),
+                None => {
+                    owners.insert(name, app_path);
+                },
+            }
+        }
+    }
+    shared
+}
+
+impl AppModule{
+    // module_name keeps the app.py of each node apart in sys.modules
+    async fn load(path: &str, module_name: &str, server: &PyObject) -> Result<(AppModule, NodeConfig), LoadError>{
+        let file = Path::new(path).join("app.py");
+        let code = fs::read_to_string(&file).await.map_err(PyErr::from)?;
+        let (config, run, close) = Python::with_gil(|py| -> Result<_, LoadError> {
+
+            py.import("unicom")?.getattr("_reset")?.call0()?;
+
+            let module = PyModule::from_code(py, &code, file.to_str().unwrap_or("app.py"), module_name)?;
+
+            if let Ok(schema) = module.getattr("settings_schema"){
+                let server = server.as_ref(py).downcast::<PyCell<PythonServer>>().map_err(PyErr::from)?;
+                if let Err(e) = server.try_borrow_mut().map_err(PyErr::from)?.apply_settings_schema(py, schema)?{
+                    return Err(LoadError::Config(e))
+                }
+            }
+
+            let config = match module.getattr("config"){
+                Ok(config) => Some(config.into_py(py)),
+                Err(_) => None,
+            };
+
+            let run = match module.getattr("run"){
+                Ok(run) => Some(run.into_py(py)),
+                Err(_) => None,
+            };
+            let close = match module.getattr("close"){
+                Ok(close) => Some(close.into_py(py)),
+                Err(_) => None,
+            };
+
+            Ok((config, run, close))
+
+        })?;
+
+        let ret = match config{
+            Some(config) => Python::with_gil(|py| -> PyResult<_> {
+
+                pyo3_asyncio::tokio::into_future(config.call1(py, (server,))?.into_ref(py))
+
+            })?.await?,
+            None => Python::with_gil(|py| -> PyResult<_> {
+                Ok(server.getattr(py, "config")?)
+            })?,
+        };
+
+        let p_config = Python::with_gil(|py| -> PyResult<PythonConfig> {
+
+            let mut p_config: PythonConfig = ret.extract(py)?;
+
+            let (apis, endpoints): (Vec<(String, PyObject)>, Vec<&PyAny>) = py.import("unicom")?.getattr("_registry")?.call0()?.extract()?;
+            for (name, object) in apis{
+                p_config.add_api(name, object)?;
+            }
+            for endpoint in endpoints{
+                let endpoint: EndPoint = depythonize(endpoint)?;
+                p_config.config.endpoints.push(endpoint);
+            }
+
+            Ok(p_config)
+
+        })?;
+
+        Ok((AppModule{
+            api_objects: p_config.api_objects,
+            api_parameters: p_config.api_parameters,
+            api_names: p_config.api_names,
+            run_object: run,
+            close_object: close,
+        }, p_config.config))
+    }
+}
+
+impl AppModule{
+    // ids are positions in api_names, so any rename, reorder or signature change would misroute requests
+    fn api_change(&self, other: &AppModule) -> Option<String>{
+        if self.api_names != other.api_names{
+            return Some(format!("apis changed ([{}] -> [{}])", self.api_names.join(", "), other.api_names.join(", ")))
+        }
+        for (index, name) in self.api_names.iter().enumerate(){
+            let current = &self.api_parameters[index];
+            let next = &other.api_parameters[index];
+            if current != next{
+                let mut methods: Vec<&String> = current.keys().chain(next.keys()).filter(|m| current.get(*m) != next.get(*m)).collect();
+                methods.sort();
+                methods.dedup();
+                let methods: Vec<&str> = methods.iter().map(|m| m.as_str()).collect();
+                return Some(format!("api {} changed its {} signature", name, methods.join(", ")))
+            }
+        }
+        None
+    }
+}
+
+static APP_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+pub struct App{
+    path: String,
+    module_name: String,
+    module: RwLock<Arc<AppModule>>,
     server: PyObject,
+    accepting: AtomicBool,
+    tracker: Arc<Tracker>,
+    workers: Option<WorkerPool>,
+    limiter: Arc<Limiter>,
     pub config: NodeConfig,
+    pub options: NodeOptions,
     pub rx: Mutex<Receiver<PythonMessage>>,
     pub tx: Sender<PythonMessage>,
     pub pending: Arc<PendingController>,
@@ -53,66 +247,173 @@ pub struct App{
 
 
 impl App{
-    pub async fn new(path: String) -> App{
+    // worker_mode is set in [workers] processes, see PythonServer
+    pub async fn new(path: String, worker_mode: bool) -> Result<App, ConfigError>{
 
-        println!("app path {}", path);
+        tracing::info!(path = %path, "loading app");
         let (tx, rx) = mpsc::channel(64);
         let pending = Arc::new(PendingController::new());
+        let tracker = Arc::new(Tracker::new());
 
-        let code = fs::read_to_string(Path::new(&path).join("app.py")).await.expect("uneable to read app.py");
-        let (config, run, close) = Python::with_gil(|py| -> PyResult<_> {
-
+        Python::with_gil(|py| -> PyResult<()> {
             py.import("sys")?.getattr("path")?
                 .downcast::<PyList>()?
                 .insert(0, &path)?;
-            
-                let module = PyModule::from_code(py, &code, "app.py", "")?;
-
-                let config = module.getattr("config").expect("config fct error").into_py(py);
-
-                let run = match module.getattr("run"){
-                    Ok(run) => Some(run.into_py(py)),
-                    Err(_) => None,
-                };
-                let close = match module.getattr("close"){
-                    Ok(close) => Some(close.into_py(py)),
-                    Err(_) => None,
-                };
-                
-                Ok((config, run, close))
-
+            module::register(py)
         }).expect("python import error");
 
-        let server = Python::with_gil(|py| -> PyResult<PyObject>{
-            Ok(Py::new(py, PythonServer::new(tx.clone(), pending.clone()))?.into_py(py))
+        let p_config = PythonConfig::new(&path)?;
+        let module_name = format!("unicom_app_{}", APP_COUNT.fetch_add(1, Ordering::SeqCst));
+        let options = p_config.options.clone();
+        let limiter = Arc::new(Limiter::new(&options));
+
+        let server = Python::with_gil(|py| -> PyResult<_>{
+            let server = PythonServer::new(tx.clone(), pending.clone(), limiter.clone(), p_config, worker_mode);
+            Ok(Py::new(py, server)?.into_py(py))
         }).expect("init server object error");
 
-        let ret = Python::with_gil(|py| -> PyResult<_> {
+        let (module, config) = match AppModule::load(&path, &module_name, &server).await{
+            Ok(loaded) => loaded,
+            Err(LoadError::Config(e)) => return Err(e),
+            Err(LoadError::Python(e)) => {
+                Python::with_gil(|py| e.print(py));
+                let file = Path::new(&path).join("app.py");
+                return Err(ConfigError::single(file.to_str().unwrap_or("app.py"), None, None, e.to_string()))
+            },
+        };
+        let config_path = Path::new(&path).join(CONFIG_PATH);
+        options.validate_apis(config_path.to_str().unwrap_or(CONFIG_PATH), &module.api_names)?;
+
+        Ok(App{
+            path,
+            module_name,
+            module: RwLock::new(Arc::new(module)),
+            server,
+            accepting: AtomicBool::new(true),
+            tracker,
+            workers: None,
+            limiter,
+            config,
+            options,
+            rx: Mutex::new(rx),
+            pending,
+            tx,
+        })
+    }
 
-            Ok(pyo3_asyncio::tokio::into_future(config.call1(py, (&server,))?.into_ref(py))?)
+    // only the serving process owns a pool, worker processes execute every api themselves
+    pub fn start_workers(&mut self){
+        let options = match &self.options.workers{
+            Some(options) => options.clone(),
+            None => return,
+        };
+        // unknown apis are refused by App::new
+        let module = self.module();
+        let apis: HashSet<u64> = options.apis.iter()
+            .filter_map(|name| module.api_names.iter().position(|api| api == name))
+            .map(|id| id as u64)
+            .collect();
+        tracing::info!(processes = options.processes, apis = ?options.apis, "starting worker pool");
+        self.workers = Some(WorkerPool::start(&self.path, options.processes, apis));
+    }
 
-        }).expect("call config failed").await.expect("config error");
+    fn module(&self) -> Arc<AppModule>{
+        self.module.read().unwrap().clone()
+    }
 
-        let p_config = Python::with_gil(|py| -> PyResult<PythonConfig> {
+    // nothing of the reload is applied before the new apis are known to match the running ones:
+    // a refused reload puts back the config, the purged modules and drops what config() created
+    pub async fn reload(&self) -> PyResult<()>{
+        let (previous, purged) = Python::with_gil(|py| -> PyResult<_> {
+            let server = self.server.as_ref(py).downcast::<PyCell<PythonServer>>()?;
+            let previous = server.try_borrow_mut()?.stage_reload(&self.path)?;
+            match PYTHON_PURGE_MODULES.call1(py, (&self.path,)){
+                Ok(purged) => Ok((previous, purged)),
+                Err(e) => {
+                    server.try_borrow_mut()?.rollback_reload(previous);
+                    Err(e)
+                },
+            }
+        })?;
+
+        let result = self.load_compatible().await;
+
+        Python::with_gil(|py| -> PyResult<()> {
+            let server = self.server.as_ref(py).downcast::<PyCell<PythonServer>>()?;
+            match &result{
+                Ok(_) => PythonServer::commit_reload(server, py),
+                Err(_) => {
+                    server.try_borrow_mut()?.rollback_reload(previous);
+                    PYTHON_PURGE_MODULES.call1(py, (&self.path,))?;
+                    py.import("sys")?.getattr("modules")?.call_method1("update", (purged,))?;
+                    Ok(())
+                },
+            }
+        })?;
 
-            Ok(ret.extract(py)?)
+        *self.module.write().unwrap() = Arc::new(result?);
+        if let Some(workers) = &self.workers{
+            workers.restart();
+        }
+        Ok(())
+    }
 
-        }).expect("config extract error");
+    // the node config advertised to the unicom server is only sent on connection,
+    // the api ids must stay stable for the swap to be transparent
+    async fn load_compatible(&self) -> PyResult<AppModule>{
+        let (module, _) = AppModule::load(&self.path, &self.module_name, &self.server).await?;
+        if let Some(change) = self.module().api_change(&module){
+            return Err(Internal::new_err(format!("{}, restart required", change)))
+        }
+        Ok(module)
+    }
 
-        App{
-            api_objects: p_config.api_objects,
-            run_object: run,
-            close_object: close,
-            server,
-            config: p_config.config,
-            rx: Mutex::new(rx),
-            pending,
-            tx,
+    fn fingerprint(&self) -> (usize, SystemTime){
+        let mut count = 0;
+        let mut last = SystemTime::UNIX_EPOCH;
+        for entry in WalkDir::new(&self.path)
+                .into_iter()
+                .filter_entry(|e| {
+                    let name = e.file_name().to_str().unwrap_or_default();
+                    e.depth() == 0 || !(name.starts_with('.') || name == "venv" || name == "__pycache__")
+                })
+                .filter_map(|e| e.ok()) {
+
+            let name = entry.file_name().to_str().unwrap_or_default();
+            if !(name.ends_with(".py") || name == "config.toml"){
+                continue
+            }
+            if let Ok(modified) = entry.metadata().and_then(|m| m.modified()){
+                count += 1;
+                if modified > last{
+                    last = modified;
+                }
+            }
+        }
+        (count, last)
+    }
+
+    pub async fn watch(&self, interval: Duration){
+        let mut current = self.fingerprint();
+        loop{
+            sleep(interval).await;
+            let fingerprint = self.fingerprint();
+            if fingerprint == current{
+                continue
+            }
+            current = fingerprint;
+            match self.reload().await{
+                Ok(()) => tracing::info!(path = %self.path, "app reloaded"),
+                Err(e) => {
+                    let error: CustomUnicomError = e.into();
+                    tracing::error!(path = %self.path, error = %error.error.description, "reload failed");
+                },
+            }
         }
     }
 
     pub fn runnable(&self) -> bool{
-        if self.run_object.is_none(){
+        if self.module().run_object.is_none(){
             false
         }else{
             true
@@ -120,29 +421,52 @@ impl App{
     }
 
     pub async fn run(&self){
-        if self.run_object.is_none(){
+        let module = self.module();
+        if module.run_object.is_none(){
             return
         }
         if let Err(e) = Python::with_gil(|py| -> PyResult<_> {
 
-            Ok(pyo3_asyncio::tokio::into_future(self.run_object.as_ref().unwrap().call1(py, (&self.server,))?.into_ref(py))?)
+            Ok(pyo3_asyncio::tokio::into_future(module.run_object.as_ref().unwrap().call1(py, (&self.server,))?.into_ref(py))?)
 
         }).expect("call run failed").await{
             let error: CustomUnicomError = e.into();
-            println!("run failed : {}", error.error.description);
+            tracing::error!(error = %error.error.description, "run failed");
         }
     }
 
-    pub async fn execute(&self, request: UnicomRequest) -> Result<Vec<u8>, CustomUnicomError>{
-        let api = match self.api_objects.get(request.id as usize){
+    // id is the unix message id of the incoming request, exposed to handlers through RequestContext
+    pub async fn execute(&self, id: u64, request: UnicomRequest) -> Result<Vec<u8>, CustomUnicomError>{
+        let module = self.module();
+        let api = match module.api_objects.get(request.id as usize){
             Some(api) => api,
             None => return Err(UnicomError::new(UnicomErrorKind::NotFound, &format!("api_id not found {}", request.id)).into()),
         };
 
+        let method: &str = request.method.clone().into();
+        let _permit = self.limiter.acquire(&module.api_names[request.id as usize], method).await?;
+
+        if let Some(workers) = &self.workers{
+            if workers.handles(request.id){
+                return Ok(workers.execute(id, request).await?)
+            }
+        }
+
+        let methods = &module.api_parameters[request.id as usize];
+        let parameters = match methods.get(method){
+            Some(specs) => validate(specs, request.parameters)?,
+            None if method == "OPTIONS" => return Ok(describe(methods).to_string().into_bytes()),
+            None => {
+                let mut allowed: Vec<&str> = methods.keys().map(|name| name.as_str()).collect();
+                allowed.sort();
+                return Err(UnicomError::new(UnicomErrorKind::MethodNotAllowed, &format!("method {} not allowed, expected one of {}", method, allowed.join(", "))).into())
+            },
+        };
+
         let ret = match Python::with_gil(|py| -> PyResult<_> {
-            let method: &str = request.method.clone().into();
             let fct = api.getattr(py, method)?;
-            pyo3_asyncio::tokio::into_future(PYTHON_EXECUTE.call1(py,(fct, pythonize(py, &request.parameters)?, &self.server,))?.as_ref(py))
+            let context = RequestContext::new(py, id, &request.node_name, &module.api_names[request.id as usize], method);
+            pyo3_asyncio::tokio::into_future(PYTHON_EXECUTE.call1(py,(fct, pythonize(py, &parameters)?, &self.server, Py::new(py, context)?,))?.as_ref(py))
         }){
             Ok(value) => {
                 match value.await{
@@ -154,6 +478,14 @@ impl App{
         };
 
         match Python::with_gil(|py| -> PyResult<PythonReturn> {
+            if let Ok(response) = ret.extract::<PyRef<Response>>(py){
+                return response.encode(py)
+            }
+            if let Ok(template) = ret.extract::<PyRef<Template>>(py){
+                let server = self.server.as_ref(py).downcast::<PyCell<PythonServer>>()?;
+                let templates = server.try_borrow()?.templates();
+                return template.encode(py, &templates)
+            }
             match ret.cast_as::<PyBytes>(py){
                 Ok(data) => Ok(PythonReturn::Binary(data.as_bytes().to_vec())),
                 Err(_) => Ok(PythonReturn::Value(depythonize(ret.as_ref(py))?)),
@@ -161,6 +493,7 @@ impl App{
         }){
             Ok(p_return) => match p_return {
                 PythonReturn::Binary(bin) => Ok(bin),
+                PythonReturn::Error(error) => Err(error.into()),
                 PythonReturn::Value(v) => {
                     match serde_json::to_string(&v){
                         Ok(v) => Ok(v.as_bytes().to_vec()),
@@ -176,13 +509,74 @@ impl App{
         
     }
 
+    // returns None once shutdown started, the guard must live as long as the request
+    pub fn accept(&self) -> Option<TrackerGuard>{
+        if !self.accepting.load(Ordering::SeqCst){
+            return None
+        }
+        Some(self.tracker.enter())
+    }
+
+    pub async fn shutdown(&self){
+        self.accepting.store(false, Ordering::SeqCst);
+        tracing::info!(in_flight = self.tracker.count(), "draining");
+        if timeout(self.options.shutdown_timeout, self.tracker.wait_idle()).await.is_err(){
+            tracing::warn!(in_flight = self.tracker.count(), "shutdown timeout, dropping remaining tasks");
+        }
+    }
+
+    // schedules stop first since their runs may still feed background workers
+    async fn cancel_schedules(&self){
+        let schedules = match Python::with_gil(|py| -> PyResult<_> {
+            let server = self.server.as_ref(py).downcast::<PyCell<PythonServer>>()?;
+            Ok(server.try_borrow()?.schedules())
+        }){
+            Ok(schedules) => schedules,
+            Err(e) => {
+                let error: CustomUnicomError = e.into();
+                tracing::error!(error = %error.error.description, "unable to cancel schedules");
+                return
+            },
+        };
+        for schedule in &schedules{
+            schedule.cancel();
+        }
+        if timeout(self.options.shutdown_timeout, join_all(schedules.iter().map(|schedule| schedule.wait()))).await.is_err(){
+            tracing::warn!("scheduled runs did not finish before the shutdown timeout");
+        }
+    }
+
+    // background workers may still send requests while draining, so they stop before the quit message
+    async fn drain_bg_workers(&self){
+        let workers = match Python::with_gil(|py| -> PyResult<_> {
+            let server = self.server.as_ref(py).downcast::<PyCell<PythonServer>>()?;
+            Ok(server.try_borrow()?.bg_workers())
+        }){
+            Ok(workers) => workers,
+            Err(e) => {
+                let error: CustomUnicomError = e.into();
+                tracing::error!(error = %error.error.description, "unable to stop background workers");
+                return
+            },
+        };
+        for worker in &workers{
+            worker.stop(true);
+        }
+        if timeout(self.options.shutdown_timeout, join_all(workers.iter().map(|worker| worker.wait()))).await.is_err(){
+            tracing::warn!("background workers did not drain before the shutdown timeout");
+        }
+    }
+
     pub async fn close(&self){
+        self.cancel_schedules().await;
+        self.drain_bg_workers().await;
         self.tx.send(PythonMessage::Quit).await.expect("send quit error");
-        if self.close_object.is_none(){
+        let module = self.module();
+        if module.close_object.is_none(){
             return
         }
         Python::with_gil(|py| -> PyResult<_> {
-            pyo3_asyncio::tokio::into_future(self.close_object.as_ref().unwrap().call1(py,(&self.server, ))?.as_ref(py))
+            pyo3_asyncio::tokio::into_future(module.close_object.as_ref().unwrap().call1(py,(&self.server, ))?.as_ref(py))
         }).expect("call close failed").await.expect("error on close");
     }
 }
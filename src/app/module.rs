@@ -0,0 +1,57 @@
+use pyo3::{prelude::*, types::PyDict, wrap_pyfunction};
+use pythonize::depythonize;
+use serde_json::Value;
+
+use crate::logging::{python_event, python_level};
+
+use super::{response::Response, template::Template, context::RequestContext, script::PYTHON_MODULE, UnicomPyError, NotFound, ParameterInvalid, InputInvalid, Internal, NotAllowed, MethodNotAllowed, Empty, Timeout};
+
+#[pymodule]
+pub fn unicom(py: Python, m: &PyModule) -> PyResult<()>{
+    m.add("UnicomPyError", py.get_type::<UnicomPyError>())?;
+    m.add("NotFound", py.get_type::<NotFound>())?;
+    m.add("ParameterInvalid", py.get_type::<ParameterInvalid>())?;
+    m.add("InputInvalid", py.get_type::<InputInvalid>())?;
+    m.add("Internal", py.get_type::<Internal>())?;
+    m.add("NotAllowed", py.get_type::<NotAllowed>())?;
+    m.add("MethodNotAllowed", py.get_type::<MethodNotAllowed>())?;
+    m.add("Empty", py.get_type::<Empty>())?;
+    m.add("Timeout", py.get_type::<Timeout>())?;
+
+    m.add_class::<Response>()?;
+    m.add_class::<Template>()?;
+    m.add_class::<RequestContext>()?;
+    m.add_function(wrap_pyfunction!(_log, m)?)?;
+
+    let source = PyModule::from_code(py, PYTHON_MODULE, "unicom.py", "unicom._source")?;
+    for name in ["FunctionApi", "api", "get", "post", "put", "delete", "patch", "head", "options", "endpoint", "_registry", "_reset", "LogHandler"]{
+        m.add(name, source.getattr(name)?)?;
+    }
+    source.getattr("_install_logging")?.call1((python_level(),))?;
+
+    Ok(())
+}
+
+pub fn fields_value(fields: Option<&PyDict>) -> Value{
+    match fields{
+        Some(fields) => depythonize(fields).unwrap_or_else(|_| Value::String(fields.to_string())),
+        None => Value::Null,
+    }
+}
+
+#[pyfunction]
+fn _log(level: &str, logger: &str, message: &str, fields: Option<&PyDict>){
+    python_event(level, logger, message, &fields_value(fields));
+}
+
+// apps hosted in the same process share one unicom module
+pub fn register(py: Python) -> PyResult<()>{
+    let modules = py.import("sys")?.getattr("modules")?.downcast::<PyDict>()?;
+    if modules.contains("unicom")?{
+        return Ok(())
+    }
+    let module = PyModule::new(py, "unicom")?;
+    unicom(py, module)?;
+    modules.set_item("unicom", module)?;
+    Ok(())
+}
@@ -0,0 +1,293 @@
+use std::{collections::HashMap, fmt};
+
+use serde_json::{json, Map, Value, Number};
+use unicom_lib::error::{UnicomError, UnicomErrorKind};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParameterType{
+    Any,
+    Int,
+    Float,
+    Str,
+    Bool,
+    Bytes,
+    Dict,
+    List(Box<ParameterType>),
+    Optional(Box<ParameterType>),
+}
+
+impl From<&str> for ParameterType{
+    fn from(kind: &str) -> Self {
+        let kind = kind.trim();
+        if let Some(inner) = kind.strip_prefix("optional[").and_then(|k| k.strip_suffix(']')){
+            return ParameterType::Optional(Box::new(inner.into()))
+        }
+        if let Some(inner) = kind.strip_prefix("list[").and_then(|k| k.strip_suffix(']')){
+            return ParameterType::List(Box::new(inner.into()))
+        }
+        match kind{
+            "int" => ParameterType::Int,
+            "float" => ParameterType::Float,
+            "str" => ParameterType::Str,
+            "bool" => ParameterType::Bool,
+            "bytes" => ParameterType::Bytes,
+            "dict" => ParameterType::Dict,
+            "list" => ParameterType::List(Box::new(ParameterType::Any)),
+            _ => ParameterType::Any,
+        }
+    }
+}
+
+impl fmt::Display for ParameterType{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self{
+            ParameterType::Any => write!(f, "any"),
+            ParameterType::Int => write!(f, "int"),
+            ParameterType::Float => write!(f, "float"),
+            ParameterType::Str => write!(f, "str"),
+            ParameterType::Bool => write!(f, "bool"),
+            ParameterType::Bytes => write!(f, "bytes"),
+            ParameterType::Dict => write!(f, "dict"),
+            ParameterType::List(inner) => write!(f, "list[{}]", inner),
+            ParameterType::Optional(inner) => write!(f, "optional[{}]", inner),
+        }
+    }
+}
+
+impl ParameterType{
+    pub fn coerce(&self, value: Value) -> Result<Value, String>{
+        match (self, value){
+            (ParameterType::Any, value) => Ok(value),
+            (ParameterType::Optional(_), Value::Null) => Ok(Value::Null),
+            (ParameterType::Optional(inner), value) => inner.coerce(value),
+
+            (ParameterType::Int, Value::Number(n)) if n.is_i64() || n.is_u64() => Ok(Value::Number(n)),
+            (ParameterType::Int, Value::Number(n)) => match n.as_f64(){
+                Some(f) if f.fract() == 0.0 => Ok(Value::Number((f as i64).into())),
+                _ => Err(format!("expected int, got float {}", n)),
+            },
+            (ParameterType::Int, Value::String(s)) => match s.trim().parse::<i64>(){
+                Ok(i) => Ok(Value::Number(i.into())),
+                Err(_) => Err(format!("expected int, got string {:?}", s)),
+            },
+
+            (ParameterType::Float, Value::Number(n)) => Ok(Value::Number(n)),
+            (ParameterType::Float, Value::String(s)) => match s.trim().parse::<f64>().ok().and_then(Number::from_f64){
+                Some(n) => Ok(Value::Number(n)),
+                None => Err(format!("expected float, got string {:?}", s)),
+            },
+
+            (ParameterType::Str, Value::String(s)) => Ok(Value::String(s)),
+            (ParameterType::Str, Value::Number(n)) => Ok(Value::String(n.to_string())),
+            (ParameterType::Str, Value::Bool(b)) => Ok(Value::String(b.to_string())),
+
+            (ParameterType::Bool, Value::Bool(b)) => Ok(Value::Bool(b)),
+            (ParameterType::Bool, Value::Number(n)) if n.as_i64() == Some(0) || n.as_i64() == Some(1) => Ok(Value::Bool(n.as_i64() == Some(1))),
+            (ParameterType::Bool, Value::String(s)) => match s.trim().to_lowercase().as_str(){
+                "true" | "1" | "yes" | "on" => Ok(Value::Bool(true)),
+                "false" | "0" | "no" | "off" => Ok(Value::Bool(false)),
+                _ => Err(format!("expected bool, got string {:?}", s)),
+            },
+
+            (ParameterType::Bytes, Value::String(s)) => Ok(Value::String(s)),
+            (ParameterType::Bytes, Value::Array(a)) => {
+                if a.iter().all(|v| v.as_u64().map_or(false, |b| b <= 255)){
+                    Ok(Value::Array(a))
+                }else{
+                    Err("expected bytes, got a list that is not made of bytes".to_string())
+                }
+            },
+
+            (ParameterType::Dict, Value::Object(o)) => Ok(Value::Object(o)),
+
+            (ParameterType::List(inner), Value::Array(a)) => {
+                let mut ret = Vec::with_capacity(a.len());
+                for (index, item) in a.into_iter().enumerate(){
+                    ret.push(inner.coerce(item).map_err(|e| format!("item {}: {}", index, e))?);
+                }
+                Ok(Value::Array(ret))
+            },
+
+            (kind, value) => Err(format!("expected {}, got {}", kind, value_kind(&value))),
+        }
+    }
+}
+
+fn value_kind(value: &Value) -> &'static str{
+    match value{
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(n) if n.is_f64() => "float",
+        Value::Number(_) => "int",
+        Value::String(_) => "string",
+        Value::Array(_) => "list",
+        Value::Object(_) => "dict",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParameterSpec{
+    pub name: String,
+    pub kind: ParameterType,
+    pub mandatory: bool,
+}
+
+impl ParameterSpec{
+    pub fn new(name: &str, kind: &str, mandatory: bool) -> ParameterSpec{
+        ParameterSpec {
+            name: name.to_string(),
+            kind: kind.into(),
+            mandatory,
+        }
+    }
+}
+
+pub fn validate(specs: &[ParameterSpec], mut parameters: Map<String, Value>) -> Result<Map<String, Value>, UnicomError>{
+    let missing: Vec<&str> = specs.iter()
+        .filter(|spec| spec.mandatory && !parameters.contains_key(&spec.name))
+        .map(|spec| spec.name.as_str())
+        .collect();
+    let unknown: Vec<&str> = parameters.keys()
+        .filter(|name| !specs.iter().any(|spec| &&spec.name == name))
+        .map(|name| name.as_str())
+        .collect();
+
+    if !missing.is_empty() || !unknown.is_empty(){
+        let mut errors = Vec::new();
+        if !missing.is_empty(){
+            errors.push(format!("missing parameters: {}", missing.join(", ")));
+        }
+        if !unknown.is_empty(){
+            errors.push(format!("unknown parameters: {}", unknown.join(", ")));
+        }
+        return Err(UnicomError::new(UnicomErrorKind::ParameterInvalid, &errors.join("; ")))
+    }
+
+    for spec in specs{
+        if let Some(value) = parameters.remove(&spec.name){
+            match spec.kind.coerce(value){
+                Ok(value) => {
+                    parameters.insert(spec.name.clone(), value);
+                },
+                Err(e) => return Err(UnicomError::new(UnicomErrorKind::ParameterInvalid, &format!("parameter '{}' {}", spec.name, e))),
+            }
+        }
+    }
+    Ok(parameters)
+}
+
+pub fn describe(methods: &HashMap<String, Vec<ParameterSpec>>) -> Value{
+    let mut names: Vec<&String> = methods.keys().collect();
+    names.sort();
+    let mut parameters = Map::new();
+    for name in &names{
+        parameters.insert(name.to_string(), methods[*name].iter().map(|spec| json!({
+            "name": spec.name,
+            "kind": spec.kind.to_string(),
+            "mandatory": spec.mandatory,
+        })).collect());
+    }
+    let mut allowed: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+    if !methods.contains_key("OPTIONS"){
+        allowed.push("OPTIONS");
+    }
+    json!({
+        "methods": allowed,
+        "parameters": parameters,
+    })
+}
+
+#[cfg(test)]
+mod tests{
+    use serde_json::{json, Map, Value};
+
+    use super::{validate, ParameterSpec, ParameterType};
+
+    #[test]
+    fn parse_kind(){
+        let cases = [
+            ("int", ParameterType::Int),
+            ("list", ParameterType::List(Box::new(ParameterType::Any))),
+            ("list[bytes]", ParameterType::List(Box::new(ParameterType::Bytes))),
+            ("optional[int]", ParameterType::Optional(Box::new(ParameterType::Int))),
+            ("optional[list[str]]", ParameterType::Optional(Box::new(ParameterType::List(Box::new(ParameterType::Str))))),
+            ("something", ParameterType::Any),
+        ];
+        for (kind, expected) in cases{
+            assert_eq!(ParameterType::from(kind), expected, "{}", kind);
+        }
+    }
+
+    #[test]
+    fn coerce(){
+        let cases = [
+            ("any", json!({"a": 1}), Some(json!({"a": 1}))),
+            ("int", json!(3), Some(json!(3))),
+            ("int", json!(3.0), Some(json!(3))),
+            ("int", json!(3.5), None),
+            ("int", json!(" 42 "), Some(json!(42))),
+            ("int", json!("4x"), None),
+            ("int", json!(true), None),
+            ("float", json!(2), Some(json!(2))),
+            ("float", json!("2.5"), Some(json!(2.5))),
+            ("float", json!("nan?"), None),
+            ("str", json!("a"), Some(json!("a"))),
+            ("str", json!(12), Some(json!("12"))),
+            ("str", json!(false), Some(json!("false"))),
+            ("str", json!(null), None),
+            ("bool", json!(1), Some(json!(true))),
+            ("bool", json!(2), None),
+            ("bool", json!("Off"), Some(json!(false))),
+            ("bool", json!("maybe"), None),
+            ("bytes", json!("abc"), Some(json!("abc"))),
+            ("bytes", json!([0, 255]), Some(json!([0, 255]))),
+            ("bytes", json!([256]), None),
+            ("dict", json!({}), Some(json!({}))),
+            ("dict", json!([]), None),
+            ("list[int]", json!(["1", 2]), Some(json!([1, 2]))),
+            ("list[int]", json!([1, "b"]), None),
+            ("list[bytes]", json!(["a", [1, 2]]), Some(json!(["a", [1, 2]]))),
+            ("optional[int]", json!(null), Some(json!(null))),
+            ("optional[int]", json!("7"), Some(json!(7))),
+            ("optional[int]", json!("x"), None),
+        ];
+        for (kind, value, expected) in cases{
+            let result = ParameterType::from(kind).coerce(value.clone());
+            assert_eq!(result.ok(), expected, "{} {}", kind, value);
+        }
+    }
+
+    #[test]
+    fn coerce_error_message(){
+        assert_eq!(ParameterType::from("int").coerce(json!([])), Err("expected int, got list".to_string()));
+        assert_eq!(ParameterType::from("list[int]").coerce(json!([1, 1.5])), Err("item 1: expected int, got float 1.5".to_string()));
+    }
+
+    fn parameters(value: Value) -> Map<String, Value>{
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn validate_parameters(){
+        let specs = [
+            ParameterSpec::new("id", "int", true),
+            ParameterSpec::new("name", "str", false),
+            ParameterSpec::new("data", "optional[bytes]", false),
+        ];
+        let cases = [
+            (json!({"id": "1"}), Ok(json!({"id": 1}))),
+            (json!({"id": 1, "name": 2, "data": null}), Ok(json!({"id": 1, "name": "2", "data": null}))),
+            (json!({}), Err("missing parameters: id")),
+            (json!({"id": 1, "other": 1}), Err("unknown parameters: other")),
+            (json!({"other": 1}), Err("missing parameters: id; unknown parameters: other")),
+            (json!({"id": "x"}), Err("parameter 'id' expected int, got string \"x\"")),
+        ];
+        for (input, expected) in cases{
+            let result = validate(&specs, parameters(input.clone()));
+            match expected{
+                Ok(expected) => assert_eq!(result.ok(), Some(parameters(expected)), "{}", input),
+                Err(expected) => assert_eq!(result.err().map(|e| e.description), Some(expected.to_string()), "{}", input),
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::collections::HashMap;
+
+use pyo3::{prelude::*, types::{PyBytes, PyString}, exceptions};
+use pythonize::depythonize;
+use unicom_lib::error::{UnicomError, UnicomErrorKind};
+
+use super::PythonReturn;
+
+// unicom only carries the body: a status >= 400 becomes the matching error, headers are refused
+#[pyclass]
+#[derive(Clone)]
+pub struct Response{
+    #[pyo3(get, set)]
+    pub body: PyObject,
+    #[pyo3(get, set)]
+    pub content_type: Option<String>,
+    #[pyo3(get)]
+    pub headers: HashMap<String, String>,
+    #[pyo3(get)]
+    pub status: u16,
+}
+
+fn check_status(status: u16) -> PyResult<()>{
+    match status{
+        200..=299 | 400..=599 => Ok(()),
+        _ => Err(exceptions::PyValueError::new_err(format!("status {} cannot be carried by the unicom protocol, use 2xx or an error status", status))),
+    }
+}
+
+fn check_headers(headers: &HashMap<String, String>) -> PyResult<()>{
+    if !headers.is_empty(){
+        return Err(exceptions::PyValueError::new_err("headers cannot be carried by the unicom protocol"))
+    }
+    Ok(())
+}
+
+#[pymethods]
+impl Response{
+    #[new]
+    #[args(content_type="None", headers="None", status="200")]
+    fn new(body: PyObject, content_type: Option<String>, headers: Option<HashMap<String, String>>, status: u16) -> PyResult<Response>{
+        let headers = headers.unwrap_or_default();
+        check_headers(&headers)?;
+        check_status(status)?;
+        Ok(Response {
+            body,
+            content_type,
+            headers,
+            status,
+        })
+    }
+
+    #[setter]
+    fn set_headers(&mut self, headers: HashMap<String, String>) -> PyResult<()>{
+        check_headers(&headers)?;
+        self.headers = headers;
+        Ok(())
+    }
+
+    #[setter]
+    fn set_status(&mut self, status: u16) -> PyResult<()>{
+        check_status(status)?;
+        self.status = status;
+        Ok(())
+    }
+
+    fn __repr__(&self) -> String{
+        format!("<Response status={} content_type={:?}>", self.status, self.content_type)
+    }
+}
+
+fn status_kind(status: u16) -> UnicomErrorKind{
+    match status{
+        400 => UnicomErrorKind::InputInvalid,
+        401 | 403 => UnicomErrorKind::NotAllowed,
+        404 => UnicomErrorKind::NotFound,
+        405 => UnicomErrorKind::MethodNotAllowed,
+        422 => UnicomErrorKind::ParameterInvalid,
+        _ => UnicomErrorKind::Internal,
+    }
+}
+
+impl Response{
+    pub fn encode(&self, py: Python) -> PyResult<PythonReturn>{
+        let body = self.body.as_ref(py);
+        if self.status >= 400{
+            let description = match body.downcast::<PyString>(){
+                Ok(text) => text.to_str()?.to_string(),
+                Err(_) => body.str()?.to_str()?.to_string(),
+            };
+            return Ok(PythonReturn::Error(UnicomError::new(status_kind(self.status), &description)))
+        }
+        if let Ok(data) = body.downcast::<PyBytes>(){
+            return Ok(PythonReturn::Binary(data.as_bytes().to_vec()))
+        }
+        // a str body goes out as is, declared as json it must already be json
+        if let Ok(text) = body.downcast::<PyString>(){
+            let text = text.to_str()?;
+            let is_json = self.content_type.as_deref().map_or(false, |c| c.starts_with("application/json"));
+            if is_json && serde_json::from_str::<serde_json::Value>(text).is_err(){
+                return Err(exceptions::PyValueError::new_err("str body declared as application/json is not valid json"))
+            }
+            return Ok(PythonReturn::Binary(text.as_bytes().to_vec()))
+        }
+        Ok(PythonReturn::Value(depythonize(body)?))
+    }
+}
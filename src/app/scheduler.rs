@@ -0,0 +1,214 @@
+use std::{sync::{Arc, Mutex, atomic::{AtomicBool, Ordering}}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use chrono::{DateTime, Local};
+use pyo3::prelude::*;
+use rand::Rng;
+use serde_json::{json, Value};
+use tokio::{sync::Notify, time::sleep};
+
+use super::{cron::Cron, CustomUnicomError};
+
+pub enum Trigger{
+    Every(Duration),
+    Cron{ expr: String, cron: Cron },
+}
+
+impl Trigger{
+    fn kind(&self) -> String{
+        match self{
+            Trigger::Every(interval) => format!("every {}s", interval.as_secs_f64()),
+            Trigger::Cron { expr, .. } => format!("cron {}", expr),
+        }
+    }
+}
+
+#[derive(Default)]
+struct ScheduleState{
+    next_run: Option<SystemTime>,
+    last_run: Option<SystemTime>,
+    running: usize,
+    runs: u64,
+    skipped: u64,
+    failed: u64,
+    last_error: Option<String>,
+}
+
+fn timestamp(time: Option<SystemTime>) -> Option<f64>{
+    time.map(|time| time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64())
+}
+
+pub struct Schedule{
+    name: String,
+    trigger: Trigger,
+    // each run is delayed by a random duration up to jitter
+    jitter: Duration,
+    // when false a run is skipped while the previous one is still going
+    overlap: bool,
+    run_now: bool,
+    state: Mutex<ScheduleState>,
+    cancelled: AtomicBool,
+    cancel: Notify,
+    finished: AtomicBool,
+    done: Notify,
+}
+
+impl Schedule{
+    pub fn new(name: &str, trigger: Trigger, jitter: Duration, overlap: bool, run_now: bool) -> Arc<Schedule>{
+        Arc::new(Schedule {
+            name: name.to_string(),
+            trigger,
+            jitter,
+            overlap,
+            run_now,
+            state: Mutex::new(ScheduleState::default()),
+            cancelled: AtomicBool::new(false),
+            cancel: Notify::new(),
+            finished: AtomicBool::new(false),
+            done: Notify::new(),
+        })
+    }
+
+    pub fn cancel(&self){
+        self.cancelled.store(true, Ordering::SeqCst);
+        self.cancel.notify_waiters();
+    }
+
+    // resolves once the schedule loop has exited and no run is left
+    pub async fn wait(&self){
+        loop{
+            let notified = self.done.notified();
+            if self.finished.load(Ordering::SeqCst) && self.state.lock().unwrap().running == 0{
+                return
+            }
+            notified.await;
+        }
+    }
+
+    pub fn stats(&self) -> Value{
+        let state = self.state.lock().unwrap();
+        json!({
+            "name": self.name,
+            "trigger": self.trigger.kind(),
+            "next_run": timestamp(state.next_run),
+            "last_run": timestamp(state.last_run),
+            "running": state.running,
+            "runs": state.runs,
+            "skipped": state.skipped,
+            "failed": state.failed,
+            "last_error": state.last_error,
+            "cancelled": self.cancelled.load(Ordering::SeqCst),
+        })
+    }
+
+    // every keeps a fixed rate from the previous tick, ticks missed meanwhile are counted as skipped,
+    // cron never goes back to the previous minute when the wall clock lags behind the monotonic sleep
+    fn next_after(&self, previous: Option<SystemTime>, now: SystemTime) -> Option<SystemTime>{
+        match &self.trigger{
+            Trigger::Every(interval) => {
+                let mut next = match previous{
+                    Some(previous) => previous + *interval,
+                    None if self.run_now => return Some(now),
+                    None => return Some(now + *interval),
+                };
+                while next < now{
+                    self.state.lock().unwrap().skipped += 1;
+                    next += *interval;
+                }
+                Some(next)
+            },
+            Trigger::Cron { cron, .. } => {
+                let after = previous.map_or(now, |previous| previous.max(now));
+                cron.next(DateTime::<Local>::from(after)).map(SystemTime::from)
+            },
+        }
+    }
+
+    fn jitter(&self) -> Duration{
+        if self.jitter.is_zero(){
+            return Duration::ZERO
+        }
+        Duration::from_secs_f64(rand::thread_rng().gen_range(0.0..self.jitter.as_secs_f64()))
+    }
+
+    // each run is its own task so a slow run never delays the next tick
+    fn start_run(self: &Arc<Self>, server: &PyObject, callable: &PyObject){
+        {
+            let mut state = self.state.lock().unwrap();
+            if !self.overlap && state.running > 0{
+                state.skipped += 1;
+                tracing::debug!(schedule = %self.name, "previous run still going, run skipped");
+                return
+            }
+            state.running += 1;
+            state.runs += 1;
+            state.last_run = Some(SystemTime::now());
+        }
+        let schedule = self.clone();
+        if let Err(e) = Python::with_gil(|py| -> PyResult<()> {
+            let future = callable.call1(py, (server.clone_ref(py),))
+                .and_then(|ret| pyo3_asyncio::tokio::into_future(ret.into_ref(py)));
+            pyo3_asyncio::tokio::future_into_py_with_locals(
+                py,
+                pyo3_asyncio::tokio::get_current_locals(py)?,
+                async move {
+                    let result = match future{
+                        Ok(future) => future.await.map(|_| ()),
+                        Err(e) => Err(e),
+                    };
+                    schedule.finish_run(result);
+                    Ok(())
+                }
+            )?;
+            Ok(())
+        }){
+            self.finish_run(Err(e));
+        }
+    }
+
+    fn finish_run(&self, result: PyResult<()>){
+        // the error is converted before locking, the conversion takes the GIL
+        let error = result.err().map(|e| -> CustomUnicomError { e.into() });
+        {
+            let mut state = self.state.lock().unwrap();
+            state.running -= 1;
+            if let Some(error) = error{
+                tracing::error!(schedule = %self.name, error = %error.error.description, "scheduled run failed");
+                state.failed += 1;
+                state.last_error = Some(error.error.description);
+            }
+        }
+        self.done.notify_waiters();
+    }
+
+    pub async fn run(self: Arc<Self>, server: PyObject, callable: PyObject){
+        let mut previous = None;
+        loop{
+            let now = SystemTime::now();
+            let next = match self.next_after(previous, now){
+                Some(next) => next,
+                None => {
+                    tracing::warn!(schedule = %self.name, "no next run, schedule stopped");
+                    break
+                },
+            };
+            previous = Some(next);
+            self.state.lock().unwrap().next_run = Some(next);
+
+            let delay = next.duration_since(now).unwrap_or_default() + self.jitter();
+            let cancelled = self.cancel.notified();
+            if self.cancelled.load(Ordering::SeqCst){
+                break
+            }
+            tokio::select! {
+                _ = sleep(delay) => {},
+                _ = cancelled => break,
+            }
+
+            self.start_run(&server, &callable);
+        }
+        self.state.lock().unwrap().next_run = None;
+        self.finished.store(true, Ordering::SeqCst);
+        self.done.notify_waiters();
+        tracing::info!(schedule = %self.name, "schedule stopped");
+    }
+}
@@ -1,24 +1,98 @@
 use pyo3::prelude::*;
 
-lazy_static! {
-    pub static ref PYTHON_SIGNATURE: PyObject = {
-        Python::with_gil(|py| -> PyObject {
-            let signature = PyModule::from_code(
-                py,
-                "
+const SIGNATURE_SOURCE: &str = "
 import inspect
+import typing
+
+KINDS = {
+    int: 'int',
+    float: 'float',
+    str: 'str',
+    bool: 'bool',
+    bytes: 'bytes',
+    list: 'list',
+    tuple: 'list',
+    set: 'list',
+    dict: 'dict',
+}
+
+# resolves string annotations (from __future__ import annotations), the raw ones are kept if that fails
+def hints(fct):
+    try:
+        resolved = typing.get_type_hints(fct)
+    except Exception:
+        resolved = {}
+    ret = {}
+    for key, parameter in inspect.signature(fct).parameters.items():
+        ret[key] = resolved.get(key, parameter.annotation)
+    return ret
+
+def kind(annotation):
+    if annotation is inspect.Parameter.empty or annotation is typing.Any:
+        return 'any'
+    if isinstance(annotation, str):
+        return annotation.replace('typing.', '').replace(' ', '').lower()
+    origin = typing.get_origin(annotation)
+    args = typing.get_args(annotation)
+    if origin is typing.Union or type(annotation).__name__ == 'UnionType':
+        not_none = [arg for arg in args if arg is not type(None)]
+        if len(not_none) == 1 and len(args) == 2:
+            return 'optional[' + kind(not_none[0]) + ']'
+        return 'any'
+    if origin in (list, tuple, set):
+        if len(args) == 1:
+            return 'list[' + kind(args[0]) + ']'
+        return 'list'
+    if origin is dict:
+        return 'dict'
+    return KINDS.get(annotation, 'any')
+
+# an annotated parameter only gets the context when annotated with RequestContext,
+# so an api parameter named request or ctx keeps its value
+def is_context(name, annotation):
+    if annotation is inspect.Parameter.empty:
+        return name in ('request', 'ctx')
+    if isinstance(annotation, str):
+        return annotation.split('.')[-1] == 'RequestContext'
+    return getattr(annotation, '__name__', None) == 'RequestContext'
+
 def signature(fct):
     ret = []
     s = inspect.signature(fct)
+    annotations = hints(fct)
     for key in s.parameters.keys():
-        if key == 'server':
+        if key == 'server' or is_context(key, annotations[key]):
+            continue
+        if s.parameters[key].kind in (inspect.Parameter.VAR_POSITIONAL, inspect.Parameter.VAR_KEYWORD):
             continue
         ret.append({
             'name': key,
-            'kind': str(s.parameters[key].annotation),
+            'kind': kind(annotations[key]),
             'mandatory': s.parameters[key].default == s.parameters[key].empty
         })
-    return ret",
+    return ret
+
+def schema(obj):
+    if isinstance(obj, dict):
+        items = [(key, value[0], value[1]) if isinstance(value, tuple) else (key, value, inspect.Parameter.empty) for key, value in obj.items()]
+    else:
+        items = [(key, value, getattr(obj, key, inspect.Parameter.empty)) for key, value in typing.get_type_hints(obj).items()]
+    ret = []
+    for name, annotation, default in items:
+        ret.append({
+            'name': name,
+            'kind': kind(annotation),
+            'mandatory': default is inspect.Parameter.empty,
+            'default': None if default is inspect.Parameter.empty else default
+        })
+    return ret";
+
+lazy_static! {
+    pub static ref PYTHON_SIGNATURE: PyObject = {
+        Python::with_gil(|py| -> PyObject {
+            let signature = PyModule::from_code(
+                py,
+                SIGNATURE_SOURCE,
                 "",
                 "",
             ).unwrap().getattr("signature").unwrap();
@@ -29,27 +103,59 @@ def signature(fct):
     };
 }
 
+lazy_static! {
+    pub static ref PYTHON_SCHEMA: PyObject = {
+        Python::with_gil(|py| -> PyObject {
+            let schema = PyModule::from_code(
+                py,
+                SIGNATURE_SOURCE,
+                "",
+                "",
+            ).unwrap().getattr("schema").unwrap();
+
+            return schema.into()
+        })
+
+    };
+}
+
 lazy_static! {
     pub static ref PYTHON_EXECUTE: PyObject = {
         Python::with_gil(|py| -> PyObject {
             let apply_fct = PyModule::from_code(
                 py,
-                "
-import asyncio
-import inspect
-def apply_fct(fct, parameters, server):
+                &format!("{}{}", SIGNATURE_SOURCE, "
+
+# bytes travel as str or list of ints in json
+def to_bytes(kind, value):
+    if value is None:
+        return None
+    if kind.startswith('optional['):
+        return to_bytes(kind[len('optional['):-1], value)
+    if kind == 'bytes' and not isinstance(value, bytes):
+        return value.encode() if isinstance(value, str) else bytes(value)
+    if kind.startswith('list[') and isinstance(value, list):
+        return [to_bytes(kind[len('list['):-1], item) for item in value]
+    return value
+
+def apply_fct(fct, parameters, server, context):
     s = inspect.signature(fct)
     b = s.bind_partial()
     b.apply_defaults()
+    annotations = hints(fct)
 
     for key in parameters.keys():   
         if key in s.parameters.keys():
-            b.arguments[key] = parameters[key]
+            b.arguments[key] = to_bytes(kind(annotations[key]), parameters[key])
 
     if 'server' in s.parameters.keys():
         b.arguments['server'] = server
 
-    return fct(*b.args, **b.kwargs)",
+    for key in s.parameters.keys():
+        if is_context(key, annotations[key]):
+            b.arguments[key] = context
+
+    return fct(*b.args, **b.kwargs)"),
                 "",
                 "",
             ).unwrap().getattr("apply_fct").unwrap();
@@ -58,4 +164,118 @@ def apply_fct(fct, parameters, server):
         })
 
     };
-}
\ No newline at end of file
+}
+
+lazy_static! {
+    pub static ref PYTHON_PURGE_MODULES: PyObject = {
+        Python::with_gil(|py| -> PyObject {
+            let purge = PyModule::from_code(
+                py,
+                "
+import os
+import sys
+# returns the purged modules so a refused reload can put them back
+def purge(path):
+    path = os.path.abspath(path)
+    purged = {}
+    for name, module in list(sys.modules.items()):
+        file = getattr(module, '__file__', None)
+        if file is None or 'site-packages' in file:
+            continue
+        if os.path.abspath(file).startswith(path + os.sep):
+            purged[name] = sys.modules.pop(name)
+    return purged",
+                "",
+                "",
+            ).unwrap().getattr("purge").unwrap();
+
+            return purge.into()
+        })
+
+    };
+}
+
+pub const PYTHON_MODULE: &str = "
+import logging
+
+_apis = {}
+_endpoints = []
+
+class FunctionApi:
+    def __init__(self, name):
+        self.name = name
+
+    def __repr__(self):
+        return '<FunctionApi ' + self.name + '>'
+
+def _register(name, obj):
+    if name in _apis and _apis[name] is not obj:
+        raise ValueError('api ' + name + ' already registered')
+    _apis[name] = obj
+
+def api(name):
+    def decorator(obj):
+        _register(name, obj() if isinstance(obj, type) else obj)
+        return obj
+    return decorator
+
+def _method(method, name):
+    def decorator(fct):
+        obj = _apis.get(name)
+        if obj is None:
+            obj = FunctionApi(name)
+            _register(name, obj)
+        elif not isinstance(obj, FunctionApi):
+            raise ValueError('api ' + name + ' is already registered as a class')
+        setattr(obj, method, fct)
+        return fct
+    return decorator
+
+def get(name):
+    return _method('GET', name)
+
+def post(name):
+    return _method('POST', name)
+
+def put(name):
+    return _method('PUT', name)
+
+def delete(name):
+    return _method('DELETE', name)
+
+def patch(name):
+    return _method('PATCH', name)
+
+def head(name):
+    return _method('HEAD', name)
+
+def options(name):
+    return _method('OPTIONS', name)
+
+def endpoint(**fields):
+    _endpoints.append(fields)
+    def decorator(obj):
+        return obj
+    return decorator
+
+def _registry():
+    return list(_apis.items()), list(_endpoints)
+
+def _reset():
+    _apis.clear()
+    _endpoints.clear()
+
+class LogHandler(logging.Handler):
+    def emit(self, record):
+        try:
+            import unicom
+            unicom._log(record.levelname, record.name, self.format(record), getattr(record, 'fields', None))
+        except Exception:
+            self.handleError(record)
+
+def _install_logging(level):
+    root = logging.getLogger()
+    if not any(isinstance(handler, LogHandler) for handler in root.handlers):
+        root.addHandler(LogHandler())
+    root.setLevel(level)
+";
@@ -1,24 +1,86 @@
-use std::{sync::Arc, collections::HashMap};
+use std::{sync::Arc, collections::HashMap, time::Duration};
 
 
-use pyo3::{prelude::*, types::PyDict, exceptions};
+use pyo3::{prelude::*, types::{PyBytes, PyDict, PyList}, exceptions};
+use pyo3_asyncio::TaskLocals;
 
 use pythonize::{pythonize, depythonize};
 use serde_json::{Map, Value};
-use tokio::sync::mpsc::{Sender, self};
-use unicom_lib::{node::{utils::pending::PendingController, message::request::UnicomRequest}, error::UnicomError};
+use tokio::{sync::mpsc::{Sender, error::{SendTimeoutError, TrySendError}}, time};
+use unicom_lib::{node::{utils::pending::PendingController, message::request::UnicomRequest}, error::{UnicomError, UnicomErrorKind}};
 
 
-use super::{PythonMessage, config::PythonConfig, CustomUnicomError, NotFound, ParameterInvalid, InputInvalid, Internal, NotAllowed, MethodNotAllowed, Empty};
+use crate::logging::python_event;
+use super::{script::PYTHON_SCHEMA, parameter::{ParameterSpec, validate}};
 
+use super::{scheduler::{Schedule, Trigger}, cron::Cron, journal::{self, Journal}, background::{BackgroundWorker, WorkerSettings, Handlers, RestartPolicy, stopped_error}, module::fields_value, template::{Templates, context_value}, limits::Limiter, PythonMessage, config::{self, PythonConfig, ConfigError, CONFIG_PATH}, CustomUnicomError, NotFound, ParameterInvalid, InputInvalid, Internal, NotAllowed, MethodNotAllowed, Empty, Timeout};
 
 
+// releases the pending slot when a request times out or its python future is cancelled
+struct PendingGuard{
+    id: u64,
+    pending: Arc<PendingController>,
+    tx: Sender<PythonMessage>,
+    done: bool,
+}
+
+impl Drop for PendingGuard{
+    fn drop(&mut self) {
+        if self.done{
+            return
+        }
+        let id = self.id;
+        let pending = self.pending.clone();
+        let tx = self.tx.clone();
+        if let Ok(handle) = tokio::runtime::Handle::try_current(){
+            handle.spawn(async move {
+                let error = UnicomError::new(UnicomErrorKind::Internal, "request cancelled");
+                if pending.update(id, Err(error)).await.is_ok(){
+                    let _ = pending.get(id).await;
+                }
+                let _ = tx.send(PythonMessage::Cancel { id }).await;
+            });
+        }
+    }
+}
+
+// python floats may be nan, inf or too large for a Duration
+fn duration_arg(name: &str, value: f64) -> PyResult<Duration>{
+    match config::seconds(value){
+        Some(duration) => Ok(duration),
+        None => Err(exceptions::PyValueError::new_err(format!("{} must be a finite number of seconds >= 0, got {}", name, value))),
+    }
+}
+
+// background workers and schedules created by config() during a reload, started once the reload is accepted
+enum Staged{
+    Worker{
+        name: String,
+        settings: WorkerSettings,
+        handlers: Handlers,
+        durable: bool,
+        locals: TaskLocals,
+    },
+    Schedule{
+        name: String,
+        schedule: Arc<Schedule>,
+        callable: PyObject,
+        locals: TaskLocals,
+    },
+}
+
 #[pyclass]
 pub struct PythonServer{
     tx: Sender<PythonMessage>,
     pending: Arc<PendingController>,
     user_data: HashMap<String, PyObject>,
-    background_worker: HashMap<String, Sender<PyObject>>,
+    background_worker: HashMap<String, Arc<BackgroundWorker>>,
+    schedules: HashMap<String, Arc<Schedule>>,
+    limiter: Arc<Limiter>,
+    // [workers] processes run config() too, background workers and schedules stay in the serving process
+    worker_mode: bool,
+    // Some while a reload runs config()
+    staged: Option<Vec<Staged>>,
 
     #[pyo3(get)]
     config: PythonConfig
@@ -26,14 +88,180 @@ pub struct PythonServer{
 }
 
 impl PythonServer{
-    pub fn new(tx: Sender<PythonMessage>, pending: Arc<PendingController>) -> PythonServer{
+    pub fn new(tx: Sender<PythonMessage>, pending: Arc<PendingController>, limiter: Arc<Limiter>, config: PythonConfig, worker_mode: bool) -> PythonServer{
         PythonServer{
             tx,
             pending,
             user_data: HashMap::new(),
             background_worker: HashMap::new(),
-            config: PythonConfig::new(),
+            schedules: HashMap::new(),
+            limiter,
+            worker_mode,
+            staged: None,
+            config,
+        }
+    }
+
+    // validates the [settings] table against the schema declared in app.py and applies its defaults
+    // a [settings] section that does not match the schema is a config error, not a python one
+    pub fn apply_settings_schema(&mut self, py: Python, schema: &PyAny) -> PyResult<Result<(), ConfigError>>{
+        let list: &PyList = PYTHON_SCHEMA.call1(py, (schema,))?.into_ref(py).downcast()?;
+        let mut specs = Vec::new();
+        let mut defaults = Map::new();
+        for dict in list{
+            let dict: &PyDict = dict.downcast()?;
+            let name: String = dict.get_item("name").unwrap().extract()?;
+            let kind: &str = dict.get_item("kind").unwrap().extract()?;
+            let mandatory: bool = dict.get_item("mandatory").unwrap().extract()?;
+            if !mandatory{
+                defaults.insert(name.clone(), depythonize(dict.get_item("default").unwrap())?);
+            }
+            specs.push(ParameterSpec::new(&name, kind, mandatory));
+        }
+
+        let settings = match &self.config.settings{
+            Value::Object(settings) => settings.clone(),
+            _ => Map::new(),
+        };
+        let mut settings = match validate(&specs, settings){
+            Ok(settings) => settings,
+            Err(e) => {
+                let path = self.config.app_path.join(CONFIG_PATH);
+                return Ok(Err(ConfigError::single(path.to_str().unwrap_or(CONFIG_PATH), None, Some("settings".to_string()), e.description)))
+            },
+        };
+        for (name, value) in defaults{
+            settings.entry(name).or_insert(value);
+        }
+        self.config.settings = Value::Object(settings);
+        Ok(Ok(()))
+    }
+
+    pub fn templates(&self) -> Arc<Templates>{
+        self.config.templates.clone()
+    }
+
+    fn bg_worker(&self, name: &str) -> PyResult<Arc<BackgroundWorker>>{
+        if self.worker_mode{
+            return Err(NotAllowed::new_err(format!("background worker {} runs in the serving process, not in [workers] processes", name)))
+        }
+        match self.background_worker.get(name){
+            Some(worker) => Ok(worker.clone()),
+            None => Err(exceptions::PyTypeError::new_err("no background worker found")),
+        }
+    }
+
+    pub fn schedules(&self) -> Vec<Arc<Schedule>>{
+        self.schedules.values().cloned().collect()
+    }
+
+    fn add_schedule(mut self_: PyRefMut<Self>, py: Python, schedule: Arc<Schedule>, name: String, callable: PyObject) -> PyResult<()>{
+        let locals = pyo3_asyncio::tokio::get_current_locals(py)?;
+        if let Some(staged) = self_.staged.as_mut(){
+            staged.push(Staged::Schedule { name, schedule, callable, locals });
+            return Ok(())
+        }
+        Self::start_schedule(self_, py, schedule, name, callable, locals)
+    }
+
+    fn start_schedule(mut self_: PyRefMut<Self>, py: Python, schedule: Arc<Schedule>, name: String, callable: PyObject, locals: TaskLocals) -> PyResult<()>{
+        if let Some(previous) = self_.schedules.insert(name, schedule.clone()){
+            previous.cancel();
+        }
+        let server = self_.into_py(py);
+        pyo3_asyncio::tokio::future_into_py_with_locals(
+            py,
+            locals,
+            async move {
+                schedule.run(server, callable).await;
+                Ok(())
+            }
+        )?;
+        Ok(())
+    }
+
+    pub fn bg_workers(&self) -> Vec<Arc<BackgroundWorker>>{
+        self.background_worker.values().cloned().collect()
+    }
+
+    // config() of the reloaded app.py sees the new config, the previous one is returned to roll back a refused reload
+    pub fn stage_reload(&mut self, app_path: &str) -> PyResult<PythonConfig>{
+        let config = match PythonConfig::new(app_path){
+            Ok(config) => config,
+            Err(e) => return Err(InputInvalid::new_err(e.to_string())),
+        };
+        self.staged = Some(Vec::new());
+        Ok(std::mem::replace(&mut self.config, config))
+    }
+
+    pub fn rollback_reload(&mut self, previous: PythonConfig){
+        self.staged = None;
+        self.config = previous;
+    }
+
+    // starts what config() created during the reload, replacing the workers and schedules of the same name
+    pub fn commit_reload(cell: &PyCell<PythonServer>, py: Python) -> PyResult<()>{
+        let staged = cell.try_borrow_mut()?.staged.take().unwrap_or_default();
+        for staged in staged{
+            let (name, result) = match staged{
+                Staged::Worker { name, settings, handlers, durable, locals } => {
+                    (name.clone(), Self::start_bg_worker(cell.try_borrow_mut()?, py, name, settings, handlers, durable, locals))
+                },
+                Staged::Schedule { name, schedule, callable, locals } => {
+                    (name.clone(), Self::start_schedule(cell.try_borrow_mut()?, py, schedule, name, callable, locals))
+                },
+            };
+            if let Err(e) = result{
+                let error: CustomUnicomError = e.into();
+                tracing::error!(name = %name, error = %error.error.description, "unable to start after reload");
+            }
         }
+        Ok(())
+    }
+
+    // a running durable worker created again with the same settings is kept and only takes the new handlers,
+    // two journals on the same file would lose records
+    fn keeps_bg_worker(&self, name: &str, settings: &WorkerSettings, handlers: &Handlers, durable: bool) -> PyResult<bool>{
+        match self.background_worker.get(name){
+            Some(previous) if previous.is_durable() && !previous.is_finished() => {
+                if durable && previous.accepts(settings, handlers){
+                    return Ok(true)
+                }
+                Err(NotAllowed::new_err(format!("durable background worker {} is still running with other settings, stop it first", name)))
+            },
+            _ => Ok(false),
+        }
+    }
+
+    fn start_bg_worker(mut self_: PyRefMut<Self>, py: Python, name: String, settings: WorkerSettings, handlers: Handlers, durable: bool, locals: TaskLocals) -> PyResult<()>{
+        if self_.keeps_bg_worker(&name, &settings, &handlers, durable)?{
+            tracing::info!(worker = %name, "durable background worker kept");
+            if let Some(previous) = self_.background_worker.get(&name){
+                previous.replace_handlers(handlers);
+            }
+            return Ok(())
+        }
+        let journal = match durable{
+            true => match Journal::open(&self_.config.app_path, &name){
+                Ok(journal) => Some(journal),
+                Err(e) => return Err(Internal::new_err(format!("unable to open journal of {} : {}", name, e))),
+            },
+            false => None,
+        };
+        let (worker, rx) = BackgroundWorker::new(&name, settings, journal, handlers);
+        if let Some(previous) = self_.background_worker.insert(name, worker.clone()){
+            previous.stop(true);
+        }
+        let server = self_.into_py(py);
+        pyo3_asyncio::tokio::future_into_py_with_locals(
+            py,
+            locals,
+            async move { 
+                worker.supervise(rx, server).await;
+                Ok(())
+             }
+        )?;
+        Ok(())
     }
 }
 
@@ -68,33 +296,79 @@ impl PythonServer{
         Empty::new_err(message)
     }
 
-    #[args(kwargs="**")]
-    fn request<'p>(&self, py: Python<'p>, node: String, api: String, method: String, kwargs: Option<&PyDict>) -> PyResult<&'p PyAny> {
+    #[getter]
+    fn settings(&self, py: Python) -> PyResult<PyObject>{
+        Ok(pythonize(py, &self.config.settings)?)
+    }
+
+    #[args(context="None")]
+    pub fn render(&self, template: &str, context: Option<&PyDict>) -> PyResult<String>{
+        match self.config.templates.render(template, &context_value(context)?){
+            Ok(text) => Ok(text),
+            Err(e) => {
+                let custom: CustomUnicomError = e.into();
+                Err(custom.into())
+            },
+        }
+    }
+
+    // running, queued and rejected requests of the node and of every limited api
+    pub fn limits(&self, py: Python) -> PyResult<PyObject>{
+        Ok(pythonize(py, &self.limiter.stats())?)
+    }
+
+    #[args(fields="**")]
+    pub fn log(&self, level: &str, message: &str, fields: Option<&PyDict>){
+        python_event(level, "server", message, &fields_value(fields));
+    }
+
+    // the unicom protocol answers a request with a single message, there is no chunked response,
+    // large payloads are received whole
+    // each argument is a python keyword argument
+    #[allow(clippy::too_many_arguments)]
+    #[args(timeout="None", raw="false", kwargs="**")]
+    fn request<'p>(&self, py: Python<'p>, node: String, api: String, method: String, timeout: Option<f64>, raw: bool, kwargs: Option<&PyDict>) -> PyResult<&'p PyAny> {
         let tx = self.tx.clone();
         let pending = self.pending.clone();
         let mut parameters = Map::new();
         if let Some(kwargs) = kwargs{
             parameters = depythonize(kwargs)?;
         }
+        let timeout = match timeout{
+            Some(timeout) if timeout <= 0.0 => None,
+            Some(timeout) => Some(duration_arg("timeout", timeout)?),
+            None => self.config.options.request_timeout,
+        };
 
         pyo3_asyncio::tokio::future_into_py_with_locals(
             py, 
             pyo3_asyncio::tokio::get_current_locals(py)?,
             async move {
                 let mut request = UnicomRequest::new();
-                request.node_name = node;
+                request.node_name = node.clone();
                 request.method = method.into();
-                request.name = api;
+                request.name = api.clone();
                 request.parameters = parameters;
 
                 let (id, notify) = pending.create().await;
+                let mut guard = PendingGuard{ id, pending: pending.clone(), tx: tx.clone(), done: false };
 
-                tx.send(PythonMessage::Request{
+                if tx.send(PythonMessage::Request{
                     id,
                     data: request,
-                }).await.unwrap();
+                }).await.is_err(){
+                    return Err(Internal::new_err("node is closed"))
+                }
 
-                notify.notified().await;
+                match timeout{
+                    Some(timeout) => {
+                        if time::timeout(timeout, notify.notified()).await.is_err(){
+                            return Err(Timeout::new_err(format!("request {}/{} timed out after {:?}", node, api, timeout)))
+                        }
+                    },
+                    None => notify.notified().await,
+                }
+                guard.done = true;
 
                 let data = match pending.get(id).await{
                     Ok(data) => data,
@@ -103,23 +377,16 @@ impl PythonServer{
                         return Err(custom.into())
                     },
                 };
-                let st = String::from_utf8(data)?;
-                //println!("receive raw: {}", st);
-
-                let value: Value = match serde_json::from_str(&st){
-                    Ok(v) => v,
-                    Err(e) => {
-                        let error : UnicomError = e.into();
-                        let custom : CustomUnicomError = error.into();
-                        return Err(custom.into())
-                    },
-                };
-                
-
                 Python::with_gil(|py: Python<'_>| -> PyResult<Py<PyAny>> {
-                    Ok(pythonize(py, &value)?)
+                    if raw{
+                        return Ok(PyBytes::new(py, &data).into())
+                    }
+                    match serde_json::from_slice::<Value>(&data){
+                        Ok(value) => Ok(pythonize(py, &value)?),
+                        Err(_) => Ok(PyBytes::new(py, &data).into()),
+                    }
+                })
             })
-        })
 
     }
 
@@ -131,59 +398,213 @@ impl PythonServer{
         self.user_data.get(&name)
     }
 
-    pub fn create_bg_worker(mut self_: PyRefMut<Self>, py: Python, name: String, callable: PyObject) -> PyResult<()>{
-        let (tx, mut rx) = mpsc::channel(64);
-        self_.background_worker.insert(name, tx);
-        let test = self_.into_py(py);
+    // each argument is a python keyword argument
+    #[allow(clippy::too_many_arguments)]
+    #[args(queue_size="64", restart="\"on_failure\"", max_restarts="None", restart_delay="1.0", on_stop="None", concurrency="1", key="None", durable="false", max_attempts="5", retry_delay="1.0")]
+    pub fn create_bg_worker(mut self_: PyRefMut<Self>, py: Python, name: String, callable: PyObject, queue_size: usize, restart: &str, max_restarts: Option<usize>, restart_delay: f64, on_stop: Option<PyObject>, concurrency: usize, key: Option<PyObject>, durable: bool, max_attempts: u32, retry_delay: f64) -> PyResult<()>{
+        if concurrency == 0{
+            return Err(exceptions::PyValueError::new_err("concurrency must be greater than 0"))
+        }
+        if self_.worker_mode{
+            tracing::debug!(worker = %name, "worker process, background worker not created");
+            return Ok(())
+        }
+        if durable && !journal::valid_name(&name){
+            return Err(exceptions::PyValueError::new_err(format!("invalid durable worker name {:?}, use letters, digits, '_', '-' and '.'", name)))
+        }
+        let settings = WorkerSettings {
+            queue_size,
+            restart: RestartPolicy::try_from(restart)?,
+            max_restarts,
+            restart_delay: duration_arg("restart_delay", restart_delay)?,
+            concurrency,
+            max_attempts: max_attempts.max(1),
+            retry_delay: duration_arg("retry_delay", retry_delay)?,
+        };
+        let handlers = Handlers { callable, key, on_stop };
+        let locals = pyo3_asyncio::tokio::get_current_locals(py)?;
+        if self_.staged.is_some(){
+            // a conflicting durable worker still fails the reload
+            self_.keeps_bg_worker(&name, &settings, &handlers, durable)?;
+            if let Some(staged) = self_.staged.as_mut(){
+                staged.push(Staged::Worker { name, settings, handlers, durable, locals });
+            }
+            return Ok(())
+        }
+        Self::start_bg_worker(self_, py, name, settings, handlers, durable, locals)
+    }
+
+    // the returned awaitable resolves once the worker has stopped
+    #[args(drain="true")]
+    pub fn stop_bg_worker<'p>(&mut self, py: Python<'p>, name: String, drain: bool) -> PyResult<&'p PyAny>{
+        let worker = self.bg_worker(&name)?;
+        worker.stop(drain);
         pyo3_asyncio::tokio::future_into_py_with_locals(
             py,
             pyo3_asyncio::tokio::get_current_locals(py)?,
-            async move { 
-                loop{
-                    let py_object = rx.recv().await;
-                    if py_object.is_none(){
-                        break
-                    }
+            async move {
+                worker.wait().await;
+                Ok(())
+            }
+        )
+    }
 
-                    let value = Python::with_gil(|py| -> PyResult<_> {
-                        
-                        pyo3_asyncio::tokio::into_future(callable.call1(py,(test.clone(), &py_object.unwrap(),))?.as_ref(py))
-                    })?;
+    pub fn list_bg_workers(&self, py: Python) -> PyResult<PyObject>{
+        let mut workers: Vec<Value> = self.background_worker.values().map(|worker| worker.stats()).collect();
+        workers.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+        Ok(pythonize(py, &workers)?)
+    }
 
-                    value.await?;
+    // callable(server) runs every interval seconds, a schedule with the same name is replaced
+    // each argument is a python keyword argument
+    #[allow(clippy::too_many_arguments)]
+    #[args(jitter="0.0", overlap="false", run_now="false")]
+    pub fn schedule_every(self_: PyRefMut<Self>, py: Python, name: String, interval: f64, callable: PyObject, jitter: f64, overlap: bool, run_now: bool) -> PyResult<()>{
+        if interval <= 0.0{
+            return Err(exceptions::PyValueError::new_err("interval must be greater than 0"))
+        }
+        let (interval, jitter) = (duration_arg("interval", interval)?, duration_arg("jitter", jitter)?);
+        if self_.worker_mode{
+            tracing::debug!(schedule = %name, "worker process, schedule not created");
+            return Ok(())
+        }
+        let schedule = Schedule::new(&name, Trigger::Every(interval), jitter, overlap, run_now);
+        Self::add_schedule(self_, py, schedule, name, callable)
+    }
+
+    // cron expression in local time, the schedule is named after the callable by default
+    #[args(name="None", jitter="0.0", overlap="false")]
+    pub fn schedule_cron(self_: PyRefMut<Self>, py: Python, cron: String, callable: PyObject, name: Option<String>, jitter: f64, overlap: bool) -> PyResult<()>{
+        let parsed = match Cron::parse(&cron){
+            Ok(parsed) => parsed,
+            Err(e) => return Err(exceptions::PyValueError::new_err(format!("invalid cron expression {:?} : {}", cron, e))),
+        };
+        let name = match name{
+            Some(name) => name,
+            None => callable.getattr(py, "__name__")?.extract(py)?,
+        };
+        if self_.worker_mode{
+            tracing::debug!(schedule = %name, "worker process, schedule not created");
+            return Ok(())
+        }
+        let schedule = Schedule::new(&name, Trigger::Cron { expr: cron, cron: parsed }, duration_arg("jitter", jitter)?, overlap, false);
+        Self::add_schedule(self_, py, schedule, name, callable)
+    }
+
+    // running calls are left to finish, returns False when no schedule has this name
+    pub fn cancel_schedule(&mut self, name: String) -> bool{
+        match self.schedules.remove(&name){
+            Some(schedule) => {
+                schedule.cancel();
+                true
+            },
+            None => false,
+        }
+    }
+
+    // name, trigger, next_run and last_run as unix timestamps, run counters and last error
+    pub fn list_schedules(&self, py: Python) -> PyResult<PyObject>{
+        let mut schedules: Vec<Value> = self.schedules.values().map(|schedule| schedule.stats()).collect();
+        schedules.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+        Ok(pythonize(py, &schedules)?)
+    }
+
+    // dead letters of a durable worker: id, item, attempts and last error
+    pub fn bg_worker_dead_letters(&self, py: Python, name: String) -> PyResult<PyObject>{
+        let entries = self.bg_worker(&name)?.dead_letters()?;
+        Ok(pythonize(py, &entries)?)
+    }
+
+    pub fn discard_dead_letter(&self, name: String, id: u64) -> PyResult<bool>{
+        self.bg_worker(&name)?.discard(id)
+    }
+
+    // the awaitable resolves to False when id is not a dead letter of the worker
+    pub fn requeue_dead_letter<'p>(&self, py: Python<'p>, name: String, id: u64) -> PyResult<&'p PyAny>{
+        let worker = self.bg_worker(&name)?;
+        let requeued = worker.requeue(py, id)?;
+        pyo3_asyncio::tokio::future_into_py_with_locals(
+            py,
+            pyo3_asyncio::tokio::get_current_locals(py)?,
+            async move {
+                let (data, item) = match requeued{
+                    Some(requeued) => requeued,
+                    None => return Ok(false),
+                };
+                // a stopped worker keeps the item in its journal for the next start
+                if data.send(item).await.is_err(){
+                    return Err(stopped_error(&name))
                 }
-                Ok(())
-             }
-        )?;
-        Ok(())
+                Ok(true)
+            }
+        )
     }
 
     pub fn send_bg_worker<'p>(&'p mut self, py: Python<'p>, name: String, object: PyObject) -> PyResult<&'p PyAny>{
-        let data = self.background_worker.get(&name);
-        if data.is_none(){
-            return Err(exceptions::PyTypeError::new_err("no background worker found"))
-        }
-        let data = data.unwrap().clone();
+        let worker = self.bg_worker(&name)?;
+        let (data, item) = worker.prepare(py, object)?;
         pyo3_asyncio::tokio::future_into_py_with_locals(
             py, 
             pyo3_asyncio::tokio::get_current_locals(py)?,
             async move {
-                data.send(object).await.unwrap();
+                if let Err(e) = data.send(item).await{
+                    worker.rejected(&e.0);
+                    return Err(stopped_error(&name))
+                }
                 Ok(())
             }
         )
     }
 
-    pub fn send_bg_worker_thread_safe(& mut self, name: String, object: PyObject) -> PyResult<()>{
-        let data = self.background_worker.get(&name);
-        if data.is_none(){
-            return Err(exceptions::PyTypeError::new_err("no background worker found"))
+    // never blocks: returns "sent", "full" when the queue is at capacity or "stopped"
+    pub fn try_send_bg_worker(&self, py: Python, name: String, object: PyObject) -> PyResult<&'static str>{
+        let worker = self.bg_worker(&name)?;
+        if worker.sender().is_none(){
+            return Ok("stopped")
         }
-        let data = data.unwrap().clone();
-
-        futures::executor::block_on(data.send(object)).unwrap();
+        let (data, item) = worker.prepare(py, object)?;
+        match data.try_send(item){
+            Ok(()) => Ok("sent"),
+            Err(TrySendError::Full(item)) => {
+                worker.rejected(&item);
+                Ok("full")
+            },
+            Err(TrySendError::Closed(item)) => {
+                worker.rejected(&item);
+                Ok("stopped")
+            },
+        }
+    }
 
-        Ok(())
+    // for foreign threads: waits for room in the queue with the GIL released, up to timeout seconds
+    #[args(timeout="None")]
+    pub fn send_bg_worker_thread_safe(&self, py: Python, name: String, object: PyObject, timeout: Option<f64>) -> PyResult<()>{
+        let running_loop = py.import("asyncio")?.getattr("_get_running_loop")?.call0()?;
+        if !running_loop.is_none() || tokio::runtime::Handle::try_current().is_ok(){
+            return Err(exceptions::PyRuntimeError::new_err("send_bg_worker_thread_safe would block the event loop, use send_bg_worker or try_send_bg_worker"))
+        }
+        let wait = timeout.map(|timeout| duration_arg("timeout", timeout)).transpose()?;
+        let worker = self.bg_worker(&name)?;
+        let (data, item) = worker.prepare(py, object)?;
+        let result = py.allow_threads(move || {
+            pyo3_asyncio::tokio::get_runtime().block_on(async move {
+                match wait{
+                    Some(wait) => data.send_timeout(item, wait).await,
+                    None => data.send(item).await.map_err(|e| SendTimeoutError::Closed(e.0)),
+                }
+            })
+        });
+        match result{
+            Ok(()) => Ok(()),
+            Err(SendTimeoutError::Timeout(item)) => {
+                worker.rejected(&item);
+                Err(Timeout::new_err(format!("background worker {} queue still full after {}s", name, timeout.unwrap_or_default())))
+            },
+            Err(SendTimeoutError::Closed(item)) => {
+                worker.rejected(&item);
+                Err(stopped_error(&name))
+            },
+        }
     }
 
 }
@@ -0,0 +1,78 @@
+use pyo3::{prelude::*, types::PyDict};
+use pythonize::depythonize;
+use serde_json::Value;
+use tera::{Context, Tera};
+use unicom_lib::error::{UnicomError, UnicomErrorKind};
+
+use super::PythonReturn;
+
+// renders the templates registered with the node under the same names,
+// the unix protocol has no render message so this happens in the node itself
+#[derive(Debug)]
+pub struct Templates{
+    tera: Tera,
+}
+
+impl Templates{
+    pub fn new(templates: Vec<(String, String)>) -> Templates{
+        let mut tera = Tera::default();
+        let files = templates.into_iter().map(|(path, name)| (path, Some(name))).collect::<Vec<_>>();
+        if let Err(e) = tera.add_template_files(files){
+            tracing::error!(error = %e, "template load error");
+        }
+        Templates { tera }
+    }
+
+    pub fn render(&self, name: &str, context: &Value) -> Result<String, UnicomError>{
+        if !self.tera.get_template_names().any(|n| n == name){
+            return Err(UnicomError::new(UnicomErrorKind::NotFound, &format!("template not found {}", name)))
+        }
+        let context = match Context::from_serialize(context){
+            Ok(context) => context,
+            Err(e) => return Err(UnicomError::new(UnicomErrorKind::InputInvalid, &format!("template context error {}", e))),
+        };
+        match self.tera.render(name, &context){
+            Ok(text) => Ok(text),
+            Err(e) => Err(UnicomError::new(UnicomErrorKind::Internal, &format!("template render error {} : {:?}", name, e))),
+        }
+    }
+}
+
+pub fn context_value(context: Option<&PyDict>) -> PyResult<Value>{
+    match context{
+        Some(context) => Ok(depythonize(context)?),
+        None => Ok(Value::Object(Default::default())),
+    }
+}
+
+#[pyclass]
+#[derive(Clone)]
+pub struct Template{
+    #[pyo3(get, set)]
+    pub name: String,
+    #[pyo3(get, set)]
+    pub context: Option<Py<PyDict>>,
+}
+
+#[pymethods]
+impl Template{
+    #[new]
+    #[args(context="None")]
+    fn new(name: String, context: Option<Py<PyDict>>) -> Template{
+        Template { name, context }
+    }
+
+    fn __repr__(&self) -> String{
+        format!("<Template {}>", self.name)
+    }
+}
+
+impl Template{
+    pub fn encode(&self, py: Python, templates: &Templates) -> PyResult<PythonReturn>{
+        let context = context_value(self.context.as_ref().map(|c| c.as_ref(py)))?;
+        match templates.render(&self.name, &context){
+            Ok(text) => Ok(PythonReturn::Binary(text.into_bytes())),
+            Err(error) => Ok(PythonReturn::Error(error)),
+        }
+    }
+}
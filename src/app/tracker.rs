@@ -0,0 +1,48 @@
+use std::sync::{Arc, atomic::{AtomicUsize, Ordering}};
+
+use tokio::sync::Notify;
+
+pub struct Tracker{
+    count: AtomicUsize,
+    idle: Notify,
+}
+
+pub struct TrackerGuard{
+    tracker: Arc<Tracker>,
+}
+
+impl Tracker{
+    pub fn new() -> Tracker{
+        Tracker {
+            count: AtomicUsize::new(0),
+            idle: Notify::new(),
+        }
+    }
+
+    pub fn enter(self: &Arc<Self>) -> TrackerGuard{
+        self.count.fetch_add(1, Ordering::SeqCst);
+        TrackerGuard { tracker: self.clone() }
+    }
+
+    pub fn count(&self) -> usize{
+        self.count.load(Ordering::SeqCst)
+    }
+
+    pub async fn wait_idle(&self){
+        loop{
+            let notified = self.idle.notified();
+            if self.count() == 0{
+                return
+            }
+            notified.await;
+        }
+    }
+}
+
+impl Drop for TrackerGuard{
+    fn drop(&mut self) {
+        if self.tracker.count.fetch_sub(1, Ordering::SeqCst) == 1{
+            self.tracker.idle.notify_waiters();
+        }
+    }
+}
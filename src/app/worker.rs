@@ -0,0 +1,257 @@
+use std::{collections::HashSet, io, os::unix::io::FromRawFd, process::Stdio, sync::Arc, time::Duration};
+
+use serde::{Serialize, de::DeserializeOwned};
+use serde_derive::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    process::{Child, ChildStdin, ChildStdout, Command},
+    sync::{Mutex, mpsc, oneshot, watch},
+    time::sleep,
+};
+use unicom_lib::{error::{UnicomError, UnicomErrorKind}, node::message::request::UnicomRequest};
+
+use super::{App, PythonMessage};
+
+extern "C" {
+    fn dup(fd: ::std::os::raw::c_int) -> ::std::os::raw::c_int;
+    fn dup2(old: ::std::os::raw::c_int, new: ::std::os::raw::c_int) -> ::std::os::raw::c_int;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WorkerRequest{
+    id: u64,
+    api: u64,
+    method: String,
+    node: String,
+    name: String,
+    parameters: Map<String, Value>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct WorkerResponse{
+    error: Option<(String, String)>,
+}
+
+impl WorkerRequest{
+    fn new(id: u64, request: UnicomRequest) -> Self {
+        let method: &str = request.method.clone().into();
+        WorkerRequest {
+            id,
+            api: request.id,
+            method: method.to_string(),
+            node: request.node_name,
+            name: request.name,
+            parameters: request.parameters,
+        }
+    }
+}
+
+impl From<WorkerRequest> for UnicomRequest{
+    fn from(request: WorkerRequest) -> Self {
+        let mut data = UnicomRequest::new();
+        data.id = request.api;
+        data.method = request.method.into();
+        data.node_name = request.node;
+        data.name = request.name;
+        data.parameters = request.parameters;
+        data
+    }
+}
+
+fn kind_from_name(name: &str) -> UnicomErrorKind{
+    match name{
+        "NotFound" => UnicomErrorKind::NotFound,
+        "ParameterInvalid" => UnicomErrorKind::ParameterInvalid,
+        "InputInvalid" => UnicomErrorKind::InputInvalid,
+        "NotAllowed" => UnicomErrorKind::NotAllowed,
+        "MethodNotAllowed" => UnicomErrorKind::MethodNotAllowed,
+        "Empty" => UnicomErrorKind::Empty,
+        _ => UnicomErrorKind::Internal,
+    }
+}
+
+// frame: u32 header length, json header, u32 body length, body
+async fn write_frame<W: AsyncWrite + Unpin, H: Serialize>(writer: &mut W, header: &H, body: &[u8]) -> io::Result<()>{
+    let header = serde_json::to_vec(header)?;
+    writer.write_u32(header.len() as u32).await?;
+    writer.write_all(&header).await?;
+    writer.write_u32(body.len() as u32).await?;
+    writer.write_all(body).await?;
+    writer.flush().await
+}
+
+async fn read_frame<R: AsyncRead + Unpin, H: DeserializeOwned>(reader: &mut R) -> io::Result<(H, Vec<u8>)>{
+    let mut header = vec![0; reader.read_u32().await? as usize];
+    reader.read_exact(&mut header).await?;
+    let mut body = vec![0; reader.read_u32().await? as usize];
+    reader.read_exact(&mut body).await?;
+    Ok((serde_json::from_slice(&header)?, body))
+}
+
+struct Job{
+    request: WorkerRequest,
+    reply: oneshot::Sender<Result<Vec<u8>, UnicomError>>,
+}
+
+struct WorkerProcess{
+    _child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl WorkerProcess{
+    fn spawn(app_path: &str) -> io::Result<WorkerProcess>{
+        let mut child = Command::new(std::env::current_exe()?)
+            .arg("worker")
+            .arg(app_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()?;
+        let stdin = child.stdin.take().ok_or_else(|| io::Error::new(io::ErrorKind::Other, "worker stdin"))?;
+        let stdout = child.stdout.take().ok_or_else(|| io::Error::new(io::ErrorKind::Other, "worker stdout"))?;
+        Ok(WorkerProcess { _child: child, stdin, stdout: BufReader::new(stdout) })
+    }
+
+    async fn call(&mut self, request: &WorkerRequest) -> io::Result<Result<Vec<u8>, UnicomError>>{
+        write_frame(&mut self.stdin, request, &[]).await?;
+        let (response, body): (WorkerResponse, Vec<u8>) = read_frame(&mut self.stdout).await?;
+        Ok(match response.error{
+            None => Ok(body),
+            Some((kind, description)) => Err(UnicomError::new(kind_from_name(&kind), &description)),
+        })
+    }
+}
+
+pub struct WorkerPool{
+    apis: HashSet<u64>,
+    jobs: mpsc::Sender<Job>,
+    restart: watch::Sender<()>,
+}
+
+impl WorkerPool{
+    pub fn start(app_path: &str, processes: usize, apis: HashSet<u64>) -> WorkerPool{
+        let (jobs, rx) = mpsc::channel(processes * 4);
+        let rx = Arc::new(Mutex::new(rx));
+        let (restart, restart_rx) = watch::channel(());
+        for index in 0..processes{
+            tokio::spawn(worker_loop(index, app_path.to_string(), rx.clone(), restart_rx.clone()));
+        }
+        WorkerPool { apis, jobs, restart }
+    }
+
+    // replaces every process once its current request is done, used after a reload of app.py
+    pub fn restart(&self){
+        let _ = self.restart.send(());
+    }
+
+    pub fn handles(&self, api: u64) -> bool{
+        self.apis.contains(&api)
+    }
+
+    pub async fn execute(&self, id: u64, request: UnicomRequest) -> Result<Vec<u8>, UnicomError>{
+        let (reply, response) = oneshot::channel();
+        if self.jobs.send(Job { request: WorkerRequest::new(id, request), reply }).await.is_err(){
+            return Err(UnicomError::new(UnicomErrorKind::Internal, "worker pool stopped"))
+        }
+        match response.await{
+            Ok(result) => result,
+            Err(_) => Err(UnicomError::new(UnicomErrorKind::Internal, "worker dropped the request")),
+        }
+    }
+}
+
+async fn worker_loop(index: usize, app_path: String, rx: Arc<Mutex<mpsc::Receiver<Job>>>, mut restart: watch::Receiver<()>){
+    let mut process: Option<WorkerProcess> = None;
+    let mut backoff = Duration::from_millis(500);
+    loop{
+        if process.is_none(){
+            match WorkerProcess::spawn(&app_path){
+                Ok(p) => {
+                    tracing::info!(worker = index, "worker process started");
+                    process = Some(p);
+                },
+                Err(e) => {
+                    tracing::error!(worker = index, error = %e, "unable to start worker process");
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(Duration::from_secs(30));
+                    continue
+                },
+            }
+        }
+
+        // recv is cancel safe, a restart while idle loses no job
+        let job = tokio::select! {
+            job = async { rx.lock().await.recv().await } => job,
+            changed = restart.changed() => {
+                // the pool is gone
+                if changed.is_err(){
+                    break
+                }
+                tracing::info!(worker = index, "restarting worker process");
+                process = None;
+                continue
+            },
+        };
+        let job = match job{
+            Some(job) => job,
+            None => break,
+        };
+
+        let result = match process.as_mut().unwrap().call(&job.request).await{
+            Ok(result) => {
+                backoff = Duration::from_millis(500);
+                result
+            },
+            Err(e) => {
+                tracing::error!(worker = index, error = %e, "worker process died");
+                process = None;
+                Err(UnicomError::new(UnicomErrorKind::Internal, &format!("worker process died : {}", e)))
+            },
+        };
+        let _ = job.reply.send(result);
+    }
+}
+
+// entry point of a worker process: requests on stdin, responses on a private copy of stdout,
+// python output written to stdout is redirected to stderr so it cannot corrupt the frames
+pub async fn serve_worker(app: Arc<App>) -> io::Result<()>{
+    let output = unsafe {
+        let fd = dup(1);
+        if fd < 0 || dup2(2, 1) < 0{
+            return Err(io::Error::last_os_error())
+        }
+        std::fs::File::from_raw_fd(fd)
+    };
+    let mut output = tokio::fs::File::from_std(output);
+    let mut input = BufReader::new(tokio::io::stdin());
+
+    {
+        let app = app.clone();
+        tokio::spawn(async move {
+            let mut rx = app.rx.lock().await;
+            while let Some(message) = rx.recv().await{
+                if let PythonMessage::Request { id, .. } = message{
+                    let error = UnicomError::new(UnicomErrorKind::NotAllowed, "server.request is not available in worker processes");
+                    let _ = app.pending.update(id, Err(error)).await;
+                }
+            }
+        });
+    }
+
+    loop{
+        let request: WorkerRequest = match read_frame(&mut input).await{
+            Ok((request, _)) => request,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        match app.execute(request.id, request.into()).await{
+            Ok(data) => write_frame(&mut output, &WorkerResponse { error: None }, &data).await?,
+            Err(e) => {
+                let error = (format!("{:?}", e.error.kind), e.error.description);
+                write_frame(&mut output, &WorkerResponse { error: Some(error) }, &[]).await?
+            },
+        }
+    }
+}
@@ -0,0 +1,344 @@
+use std::{env, fmt, fs, path::Path};
+
+use unicom_lib::config::Config;
+
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/unicom/config.toml";
+
+// environment fallbacks for --socket, --config and --profile
+const ENV_SOCKET: &str = "UNICOM_SOCKET";
+const ENV_CONFIG: &str = "UNICOM_CONFIG";
+const ENV_PROFILE: &str = "UNICOM_PROFILE";
+
+pub const USAGE: &str = "usage: unicom-python-bin <command> [options]
+
+commands:
+    run <app_dir> [socket]
+                          load app.py and serve it on the unicom socket
+    check <app_dir>       load app.py and config.toml, print the node config and exit
+    describe <app_dir>    dump the endpoints and apis of the node as json
+    multi <app_dir|glob>...
+                          serve several apps from one process, one node per app
+
+options:
+    -c, --config <path>   unicom config file (default /etc/unicom/config.toml)
+    -s, --socket <path>   unix socket of the unicom server, overrides the config file
+    -p, --profile <name>  also load config.<name>.toml from the app directory
+    -r, --reload          reload app.py when a python file changes (run only)
+    --log-level <filter>  log level or filter directives (default info, or [log] level)
+    --log-format <format> text or json (default text, or [log] format)
+    -h, --help            print this help
+
+environment:
+    UNICOM_SOCKET, UNICOM_CONFIG, UNICOM_PROFILE
+                          defaults for --socket, --config and --profile
+    UNICOM_<KEY>[__<KEY>] override a config.toml value, e.g. UNICOM_TAGS__ENV=prod,
+                          UNICOM_NAME is ignored by multi";
+
+#[derive(Debug, PartialEq)]
+pub enum Command{
+    Run,
+    Check,
+    Describe,
+    Multi,
+    // internal: serves the [workers] apis of <app_dir> over stdin/stdout
+    Worker,
+    Help,
+}
+
+#[derive(Debug)]
+pub struct Cli{
+    pub command: Command,
+    pub app_path: String,
+    pub app_paths: Vec<String>,
+    pub config_path: String,
+    pub socket: Option<String>,
+    pub reload: bool,
+    pub log_level: Option<String>,
+    pub log_format: Option<String>,
+    pub profile: Option<String>,
+}
+
+#[derive(Debug)]
+pub struct CliError{
+    message: String,
+}
+
+impl CliError{
+    fn new(message: &str) -> CliError{
+        CliError { message: message.to_string() }
+    }
+}
+
+impl fmt::Display for CliError{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Cli{
+    pub fn from_env() -> Result<Cli, CliError>{
+        Cli::parse(env::args().skip(1), |key| env::var(key).ok())
+    }
+
+    // var reads the environment fallbacks, tests pass their own
+    pub fn parse<I: Iterator<Item = String>, F: Fn(&str) -> Option<String>>(mut args: I, var: F) -> Result<Cli, CliError>{
+        let mut cli = Cli{
+            command: Command::Help,
+            app_path: String::new(),
+            app_paths: Vec::new(),
+            config_path: var(ENV_CONFIG).unwrap_or_else(|| DEFAULT_CONFIG_PATH.to_string()),
+            socket: var(ENV_SOCKET),
+            reload: false,
+            log_level: None,
+            log_format: None,
+            profile: var(ENV_PROFILE),
+        };
+        let mut positionals = Vec::new();
+
+        while let Some(arg) = args.next(){
+            match arg.as_str(){
+                "-h" | "--help" => return Ok(cli),
+                "-r" | "--reload" => cli.reload = true,
+                "-c" | "--config" => cli.config_path = args.next().ok_or_else(|| CliError::new("--config expects a path"))?,
+                "--log-level" => cli.log_level = Some(args.next().ok_or_else(|| CliError::new("--log-level expects a filter"))?),
+                "--log-format" => cli.log_format = Some(args.next().ok_or_else(|| CliError::new("--log-format expects text or json"))?),
+                "-p" | "--profile" => cli.profile = Some(args.next().ok_or_else(|| CliError::new("--profile expects a name"))?),
+                "-s" | "--socket" => cli.socket = Some(args.next().ok_or_else(|| CliError::new("--socket expects a path"))?),
+                _ if arg.starts_with('-') => return Err(CliError::new(&format!("unknown option {}", arg))),
+                _ => positionals.push(arg),
+            }
+        }
+
+        let mut positionals = positionals.into_iter();
+        cli.command = match positionals.next().as_deref(){
+            None => return Ok(cli),
+            Some("run") => Command::Run,
+            Some("check") => Command::Check,
+            Some("describe") => Command::Describe,
+            Some("worker") => Command::Worker,
+            Some("multi") => {
+                // apps share the unicom registry of the process, concurrent reloads would mix their apis
+                if cli.reload{
+                    return Err(CliError::new("--reload is not supported by multi"))
+                }
+                for pattern in positionals{
+                    cli.app_paths.extend(expand(&pattern)?);
+                }
+                if cli.app_paths.is_empty(){
+                    return Err(CliError::new("missing <app_dir>"))
+                }
+                cli.command = Command::Multi;
+                return Ok(cli)
+            },
+            Some("help") => return Ok(cli),
+            // legacy form: <app_dir> [socket]
+            Some(path) => {
+                cli.app_path = path.to_string();
+                Command::Run
+            },
+        };
+
+        if cli.app_path.is_empty(){
+            cli.app_path = positionals.next().ok_or_else(|| CliError::new("missing <app_dir>"))?;
+        }
+        if cli.command == Command::Run{
+            if let Some(socket) = positionals.next(){
+                cli.socket = Some(socket);
+            }
+        }
+        if let Some(arg) = positionals.next(){
+            return Err(CliError::new(&format!("unexpected argument {}", arg)))
+        }
+
+        Ok(cli)
+    }
+
+    pub fn is_multi(&self) -> bool{
+        self.command == Command::Multi
+    }
+
+    // the profile is read back from the environment by the app config loader
+    pub fn apply_profile(&self){
+        if let Some(profile) = &self.profile{
+            env::set_var(ENV_PROFILE, profile);
+        }
+    }
+
+    // --config and --socket are given from where the command runs, before run enters the app directory
+    pub fn resolve_paths(&mut self, cwd: &Path){
+        self.config_path = cwd.join(&self.config_path).to_string_lossy().into_owned();
+        self.socket = self.socket.as_ref().map(|socket| cwd.join(socket).to_string_lossy().into_owned());
+    }
+
+    pub fn stream_path(&self) -> Result<String, CliError>{
+        if let Some(socket) = &self.socket{
+            return Ok(socket.clone())
+        }
+        let content = std::fs::read_to_string(&self.config_path)
+            .map_err(|e| CliError::new(&format!("unable to read {} : {}", self.config_path, e)))?;
+        let config: Config = toml::from_str(&content)
+            .map_err(|e| CliError::new(&format!("invalid config {} : {}", self.config_path, e)))?;
+        Ok(config.unix_stream_path)
+    }
+}
+
+fn matches(pattern: &str, name: &str) -> bool{
+    match pattern.split_once('*'){
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            if !name.starts_with(prefix){
+                return false
+            }
+            let name = &name[prefix.len()..];
+            (0..=name.len()).filter(|i| name.is_char_boundary(*i)).any(|i| matches(rest, &name[i..]))
+        },
+    }
+}
+
+// expands a '*' in the last path component to the matching app directories
+fn expand(pattern: &str) -> Result<Vec<String>, CliError>{
+    let path = Path::new(pattern);
+    let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    if !name.contains('*'){
+        return Ok(vec![pattern.to_string()])
+    }
+    let parent = match path.parent(){
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let entries = fs::read_dir(parent).map_err(|e| CliError::new(&format!("unable to read {} : {}", parent.display(), e)))?;
+    let mut paths: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join("app.py").is_file())
+        .filter(|entry| entry.file_name().to_str().map_or(false, |n| matches(name, n)))
+        .filter_map(|entry| entry.path().to_str().map(|p| p.to_string()))
+        .collect();
+    paths.sort();
+    if paths.is_empty(){
+        return Err(CliError::new(&format!("no app directory matches {}", pattern)))
+    }
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests{
+    use std::path::Path;
+
+    use super::{matches, Cli, Command};
+
+    fn parse(args: &str) -> Result<Cli, String>{
+        Cli::parse(args.split_whitespace().map(|arg| arg.to_string()), |_| None).map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn environment(){
+        let var = |key: &str| match key{
+            "UNICOM_SOCKET" => Some("/run/env.sock".to_string()),
+            "UNICOM_CONFIG" => Some("/etc/env.toml".to_string()),
+            "UNICOM_PROFILE" => Some("prod".to_string()),
+            _ => None,
+        };
+        let cli = Cli::parse(["run", "app"].iter().map(|arg| arg.to_string()), var).unwrap();
+        assert_eq!(cli.socket.as_deref(), Some("/run/env.sock"));
+        assert_eq!(cli.config_path, "/etc/env.toml");
+        assert_eq!(cli.profile.as_deref(), Some("prod"));
+
+        let cli = Cli::parse(["run", "-s", "/tmp/u.sock", "-p", "dev", "app"].iter().map(|arg| arg.to_string()), var).unwrap();
+        assert_eq!(cli.socket.as_deref(), Some("/tmp/u.sock"));
+        assert_eq!(cli.profile.as_deref(), Some("dev"));
+
+        let cli = parse("run app").unwrap();
+        assert_eq!(cli.config_path, super::DEFAULT_CONFIG_PATH);
+        assert_eq!(cli.socket, None);
+    }
+
+    #[test]
+    fn commands(){
+        let cases = [
+            ("", Command::Help, ""),
+            ("help", Command::Help, ""),
+            ("run --help", Command::Help, ""),
+            ("run app", Command::Run, "app"),
+            ("app", Command::Run, "app"),
+            ("check app", Command::Check, "app"),
+            ("describe app", Command::Describe, "app"),
+            ("worker app", Command::Worker, "app"),
+        ];
+        for (args, command, app_path) in cases{
+            let cli = parse(args).unwrap();
+            assert_eq!(cli.command, command, "{}", args);
+            assert_eq!(cli.app_path, app_path, "{}", args);
+        }
+    }
+
+    #[test]
+    fn options(){
+        let cli = parse("run -r -c my.toml -p dev --log-level debug --log-format json app").unwrap();
+        assert!(cli.reload);
+        assert_eq!(cli.config_path, "my.toml");
+        assert_eq!(cli.profile.as_deref(), Some("dev"));
+        assert_eq!(cli.log_level.as_deref(), Some("debug"));
+        assert_eq!(cli.log_format.as_deref(), Some("json"));
+    }
+
+    #[test]
+    fn socket(){
+        let cases = ["run app /tmp/u.sock", "app /tmp/u.sock", "run --socket /tmp/u.sock app", "-s /tmp/u.sock run app"];
+        for args in cases{
+            assert_eq!(parse(args).unwrap().socket.as_deref(), Some("/tmp/u.sock"), "{}", args);
+        }
+    }
+
+    #[test]
+    fn relative_paths(){
+        let mut cli = parse("run -c conf/unicom.toml -s ./unicom.sock app").unwrap();
+        cli.resolve_paths(Path::new("/home/user"));
+        assert_eq!(cli.config_path, "/home/user/conf/unicom.toml");
+        assert_eq!(cli.socket.as_deref(), Some("/home/user/./unicom.sock"));
+
+        let mut cli = parse("run -c /etc/unicom.toml -s /run/unicom.sock app").unwrap();
+        cli.resolve_paths(Path::new("/home/user"));
+        assert_eq!(cli.config_path, "/etc/unicom.toml");
+        assert_eq!(cli.socket.as_deref(), Some("/run/unicom.sock"));
+    }
+
+    #[test]
+    fn multi(){
+        let cli = parse("multi a b").unwrap();
+        assert!(cli.is_multi());
+        assert_eq!(cli.app_paths, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn errors(){
+        let cases = [
+            ("run", "missing <app_dir>"),
+            ("multi", "missing <app_dir>"),
+            ("multi -r a b", "--reload is not supported by multi"),
+            ("run app sock extra", "unexpected argument extra"),
+            ("check app sock", "unexpected argument sock"),
+            ("run --bogus app", "unknown option --bogus"),
+            ("run app --config", "--config expects a path"),
+            ("run app -s", "--socket expects a path"),
+        ];
+        for (args, error) in cases{
+            assert_eq!(parse(args).err().as_deref(), Some(error), "{}", args);
+        }
+    }
+
+    #[test]
+    fn glob(){
+        let cases = [
+            ("*", "app", true),
+            ("app_*", "app_one", true),
+            ("app_*", "other", false),
+            ("*_api", "users_api", true),
+            ("a*c*e", "abcde", true),
+            ("a*c*e", "abcd", false),
+            ("app", "app", true),
+        ];
+        for (pattern, name, expected) in cases{
+            assert_eq!(matches(pattern, name), expected, "{} {}", pattern, name);
+        }
+    }
+}
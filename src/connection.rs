@@ -0,0 +1,111 @@
+use std::{collections::HashSet, sync::atomic::{AtomicBool, Ordering}, time::Duration};
+
+use tokio::{net::{UnixStream, unix::{OwnedReadHalf, OwnedWriteHalf}}, sync::Mutex, io::AsyncWriteExt, time::sleep};
+use unicom_lib::{arch::unix::{write_init, write_message, UnixMessage}, error::{UnicomError, UnicomErrorKind}, node::{NodeConfig, message::request::UnicomRequest, utils::pending::PendingController}};
+
+const BACKOFF_MIN: Duration = Duration::from_millis(500);
+const BACKOFF_MAX: Duration = Duration::from_secs(30);
+
+pub struct Connection{
+    stream_path: String,
+    writer: Mutex<Option<OwnedWriteHalf>>,
+    sent: std::sync::Mutex<HashSet<u64>>,
+    closing: AtomicBool,
+}
+
+impl Connection{
+    pub fn new(stream_path: String) -> Connection{
+        Connection {
+            stream_path,
+            writer: Mutex::new(None),
+            sent: std::sync::Mutex::new(HashSet::new()),
+            closing: AtomicBool::new(false),
+        }
+    }
+
+    pub fn close(&self){
+        self.closing.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_closing(&self) -> bool{
+        self.closing.load(Ordering::SeqCst)
+    }
+
+    // retries with exponential backoff until the node is registered or closing
+    pub async fn connect(&self, config: &NodeConfig) -> Option<OwnedReadHalf>{
+        let mut backoff = BACKOFF_MIN;
+        loop{
+            if self.is_closing(){
+                return None
+            }
+            match UnixStream::connect(&self.stream_path).await{
+                Ok(stream) => {
+                    let (reader, mut writer) = stream.into_split();
+                    match write_init(&mut writer, config).await{
+                        Ok(_) => {
+                            tracing::info!(path = %self.stream_path, "connected");
+                            *self.writer.lock().await = Some(writer);
+                            return Some(reader)
+                        },
+                        Err(e) => tracing::warn!(path = %self.stream_path, error = ?e, "write init error"),
+                    }
+                },
+                Err(e) => tracing::warn!(path = %self.stream_path, error = %e, retry_in = ?backoff, "unable to connect"),
+            }
+            sleep(backoff).await;
+            backoff = (backoff * 2).min(BACKOFF_MAX);
+        }
+    }
+
+    pub async fn disconnect(&self, pending: &PendingController){
+        *self.writer.lock().await = None;
+        let ids: Vec<u64> = self.sent.lock().unwrap().drain().collect();
+        for id in ids{
+            let error = UnicomError::new(UnicomErrorKind::Internal, "connection to the unicom server lost");
+            if let Err(e) = pending.update(id, Err(error)).await{
+                tracing::debug!(id, error = ?e, "pending request already gone");
+            }
+        }
+    }
+
+    pub async fn write(&self, message: UnixMessage) -> Result<(), UnicomError>{
+        let mut writer = self.writer.lock().await;
+        let writer = match writer.as_mut(){
+            Some(writer) => writer,
+            None => return Err(UnicomError::new(UnicomErrorKind::Internal, "not connected to the unicom server")),
+        };
+        match write_message(writer, message).await{
+            Ok(_) => Ok(()),
+            Err(e) => Err(UnicomError::new(UnicomErrorKind::Internal, &format!("write error {:?}", e))),
+        }
+    }
+
+    pub async fn flush(&self){
+        if let Some(writer) = self.writer.lock().await.as_mut(){
+            if let Err(e) = writer.flush().await{
+                tracing::error!(error = ?e, "error flush writer");
+            }
+        }
+    }
+
+    pub async fn send_request(&self, pending: &PendingController, id: u64, data: UnicomRequest){
+        self.sent.lock().unwrap().insert(id);
+        if let Err(error) = self.write(UnixMessage::Request { id, data }).await{
+            self.sent.lock().unwrap().remove(&id);
+            if let Err(e) = pending.update(id, Err(error)).await{
+                tracing::debug!(id, error = ?e, "pending request already gone");
+            }
+        }
+    }
+
+    pub fn forget(&self, id: u64){
+        self.sent.lock().unwrap().remove(&id);
+    }
+
+    pub async fn resolve(&self, pending: &PendingController, id: u64, result: Result<Vec<u8>, UnicomError>){
+        self.sent.lock().unwrap().remove(&id);
+        if let Err(e) = pending.update(id, result).await{
+            tracing::debug!(id, error = ?e, "pending request already gone");
+        }
+    }
+}
@@ -0,0 +1,69 @@
+use serde_json::Value;
+use tracing::level_filters::LevelFilter;
+use tracing_subscriber::EnvFilter;
+
+use crate::app::config::LogModel;
+
+// the most verbose of plain levels, filter directives are combined
+fn verbose(levels: &[String]) -> Option<String>{
+    let parsed: Option<Vec<LevelFilter>> = levels.iter().map(|level| level.parse().ok()).collect();
+    match parsed{
+        Some(parsed) => parsed.into_iter().max().map(|level| level.to_string()),
+        None => Some(levels.join(",")),
+    }
+}
+
+// cli values take precedence over the [log] section of the app config.toml, there is one
+// subscriber per process so in multi mode the sections of every app are combined
+pub fn init(level: Option<String>, format: Option<String>, sections: Vec<LogModel>){
+    let levels: Vec<String> = sections.iter().filter_map(|section| section.level.clone()).collect();
+    let formats: Vec<String> = sections.iter().filter_map(|section| section.format.clone()).collect();
+    let level_conflict = level.is_none() && levels.iter().any(|l| l != &levels[0]);
+    let format_conflict = format.is_none() && formats.iter().any(|f| f != &formats[0]);
+
+    let level = level.or_else(|| verbose(&levels)).unwrap_or_else(|| "info".to_string());
+    let format = format.or_else(|| formats.first().cloned()).unwrap_or_else(|| "text".to_string());
+
+    let filter = EnvFilter::try_new(&level).unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr);
+
+    if format == "json"{
+        builder.json().init();
+    }else{
+        builder.init();
+    }
+
+    if level_conflict{
+        tracing::warn!(levels = ?levels, level = %level, "apps set different [log] levels, using the most verbose");
+    }
+    if format_conflict{
+        tracing::warn!(formats = ?formats, format = %format, "apps set different [log] formats, using the first one");
+    }
+}
+
+pub fn python_level() -> u8{
+    let level = LevelFilter::current();
+    if level >= LevelFilter::DEBUG{
+        10
+    }else if level >= LevelFilter::INFO{
+        20
+    }else if level >= LevelFilter::WARN{
+        30
+    }else if level >= LevelFilter::ERROR{
+        40
+    }else{
+        50
+    }
+}
+
+pub fn python_event(level: &str, logger: &str, message: &str, fields: &Value){
+    match level.to_uppercase().as_str(){
+        "CRITICAL" | "FATAL" | "ERROR" => tracing::error!(target: "python", logger, %fields, "{}", message),
+        "WARNING" | "WARN" => tracing::warn!(target: "python", logger, %fields, "{}", message),
+        "DEBUG" => tracing::debug!(target: "python", logger, %fields, "{}", message),
+        "TRACE" => tracing::trace!(target: "python", logger, %fields, "{}", message),
+        _ => tracing::info!(target: "python", logger, %fields, "{}", message),
+    }
+}
@@ -1,15 +1,22 @@
 #[macro_use]
 extern crate lazy_static;
 
-use std::{sync::Arc, env, time::Duration};
+use std::{sync::Arc, env, path::Path, time::Duration, process::exit};
 
-use app::{App, PythonMessage};
+use app::{App, PythonMessage, config::{ConfigModel, CONFIG_PATH, ENV_MULTI}, worker, shared_modules};
+use cli::{Cli, Command, USAGE};
+use connection::Connection;
 use pyo3::prelude::*;
-use tokio::{net::UnixStream, sync::{Mutex, Notify}, signal, time::sleep};
+use tracing::Instrument;
+use futures::{future::join_all, stream::{FuturesUnordered, StreamExt}};
+use tokio::{sync::Notify, signal, task::JoinHandle, time::timeout};
 
-use unicom_lib::{arch::unix::{write_init, read_message, UnixMessage, write_message}, config::Config};
+use unicom_lib::{arch::unix::{read_message, UnixMessage}, error::{UnicomError, UnicomErrorKind}};
 
 mod app;
+mod cli;
+mod connection;
+mod logging;
 
 extern "C" {
     pub fn setpgrp() -> ::std::os::raw::c_int;
@@ -17,22 +24,119 @@ extern "C" {
 
 #[pyo3_asyncio::tokio::main(flavor = "multi_thread", worker_threads = 5)]
 async fn main() -> PyResult<()> {
+    let mut cli = match Cli::from_env(){
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            exit(2);
+        },
+    };
+    match env::current_dir(){
+        Ok(cwd) => cli.resolve_paths(&cwd),
+        Err(e) => {
+            eprintln!("unable to read the current directory : {}", e);
+            exit(1);
+        },
+    }
+
+    if cli.command != Command::Help && !cli.is_multi(){
+        if let Err(e) = env::set_current_dir(&cli.app_path){
+            eprintln!("unable to enter app directory {} : {}", cli.app_path, e);
+            exit(1);
+        }
+    }
+
+    let app_paths = match cli.is_multi(){
+        true => cli.app_paths.clone(),
+        false => vec!["./".to_string()],
+    };
+
+    if cli.command != Command::Help{
+        cli.apply_profile();
+        if cli.is_multi(){
+            env::set_var(ENV_MULTI, "1");
+            let shared = shared_modules(&app_paths);
+            if !shared.is_empty(){
+                eprintln!("apps share sys.modules in multi mode, rename the modules or run the apps separately\n{}", shared.join("\n"));
+                exit(1);
+            }
+        }
+
+        let mut configs = Vec::new();
+        for app_path in &app_paths{
+            let config_path = Path::new(app_path).join(CONFIG_PATH);
+            match ConfigModel::load(config_path.to_str().unwrap_or(CONFIG_PATH)){
+                Ok(config) => configs.push(config),
+                Err(e) => {
+                    eprintln!("invalid configuration\n{}", e);
+                    exit(1);
+                },
+            }
+        }
+
+        logging::init(cli.log_level.clone(), cli.log_format.clone(), configs.into_iter().filter_map(|config| config.log).collect());
+    }
+
+    match cli.command{
+        Command::Help => println!("{}", USAGE),
+        Command::Check => {
+            let app = load_app("./".to_string(), false).await;
+            println!("{:#?}", app.config);
+        },
+        Command::Describe => {
+            let app = load_app("./".to_string(), false).await;
+            match serde_json::to_string_pretty(&app.config){
+                Ok(description) => println!("{}", description),
+                Err(e) => {
+                    eprintln!("unable to serialize node config : {}", e);
+                    exit(1);
+                },
+            }
+        },
+        Command::Run | Command::Multi => {
+            let stream_path = match cli.stream_path(){
+                Ok(stream_path) => stream_path,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    exit(1);
+                },
+            };
+            run(stream_path, app_paths, cli.reload).await?;
+        },
+        Command::Worker => {
+            // the parent handles ctrl-c for the whole process group, workers stop on stdin eof
+            tokio::spawn(signal::ctrl_c());
+            let app = Arc::new(load_app("./".to_string(), true).await);
+            let result = worker::serve_worker(app.clone()).await;
+            app.close().await;
+            if let Err(e) = result{
+                tracing::error!(error = %e, "worker stopped");
+                exit(1);
+            }
+        },
+    }
+
+    Ok(())
+}
+
+// app.py and [settings] errors are reported like the config.toml ones
+async fn load_app(path: String, worker_mode: bool) -> App{
+    match App::new(path, worker_mode).await{
+        Ok(app) => app,
+        Err(e) => {
+            eprintln!("invalid configuration\n{}", e);
+            exit(1);
+        },
+    }
+}
+
+async fn run(stream_path: String, app_paths: Vec<String>, reload: bool) -> PyResult<()> {
     unsafe {
         setpgrp();
     }
 
+    // signals stop every node, a node whose run() returns or whose config is refused only stops itself
     let close_notify = Arc::new(Notify::new());
-    let args: Vec<String> = env::args().collect();
-    let stream_path: String;
-    let app_path: String = args[1].clone();
-    if args.len() <= 1{
-        let content = std::fs::read_to_string("/etc/unicom/config.toml").unwrap();
-        let config: Config = toml::from_str(&content).unwrap();
-        stream_path = config.unix_stream_path.clone();
-    }
-    else{
-        stream_path = args[2].clone();
-    }
 
     {
         let close_notify = close_notify.clone();
@@ -48,19 +152,102 @@ async fn main() -> PyResult<()> {
             let mut stream = signal::unix::signal(signal::unix::SignalKind::terminate()).unwrap();
             stream.recv().await;
             close_notify.notify_one();
-            println!("receive sigterm");
+            tracing::info!("receive sigterm");
         });
     }
 
-    let (mut reader,mut writer) = UnixStream::connect(&stream_path).await.unwrap().into_split();
-    let app = Arc::new(App::new(app_path).await);
+    let mut nodes = Vec::new();
+    for app_path in app_paths{
+        let mut app = load_app(app_path, false).await;
+        app.start_workers();
+        let app = Arc::new(app);
+        let connection = Arc::new(Connection::new(stream_path.clone()));
+        let node_close = Arc::new(Notify::new());
+        let task_exchange = serve(app.clone(), connection.clone(), node_close.clone())?;
+        nodes.push(Some((app, connection, task_exchange, node_close)));
+    }
 
-    write_init(&mut writer, &app.config).await.expect("write init error");
+    for (app, _, _, node_close) in nodes.iter().flatten(){
+        if app.runnable(){
+            Python::with_gil(|py| -> PyResult<()> {
+                let app = app.clone();
+                let node_close = node_close.clone();
+                pyo3_asyncio::tokio::future_into_py_with_locals(
+                    py,
+                    pyo3_asyncio::tokio::get_current_locals(py)?,
+                    async move { 
+                        app.run().await;
+                        node_close.notify_one();
+                        Ok(())
+                    }
+                )?;
+                Ok(())
+            }).unwrap_or_default();
+        }
 
-    let writer = Arc::new(Mutex::new(writer));
+        if reload{
+            Python::with_gil(|py| -> PyResult<()> {
+                let app = app.clone();
+                pyo3_asyncio::tokio::future_into_py_with_locals(
+                    py,
+                    pyo3_asyncio::tokio::get_current_locals(py)?,
+                    async move { 
+                        app.watch(Duration::from_secs(1)).await;
+                        Ok(())
+                    }
+                )?;
+                Ok(())
+            }).unwrap_or_default();
+        }
+    }
+
+    let mut stopped: FuturesUnordered<_> = nodes.iter().flatten().enumerate()
+        .map(|(index, (_, _, _, node_close))| {
+            let node_close = node_close.clone();
+            async move {
+                node_close.notified().await;
+                index
+            }
+        })
+        .collect();
+
+    loop{
+        tokio::select! {
+            _ = close_notify.notified() => break,
+            Some(index) = stopped.next() => {
+                if let Some((app, connection, task_exchange, _)) = nodes[index].take(){
+                    tracing::info!(node = %app.config.name, "node stopped");
+                    app.shutdown().await;
+                    close_node(app, connection, task_exchange).await;
+                }
+                if nodes.iter().all(|node| node.is_none()){
+                    break
+                }
+            },
+        }
+    }
+
+    let nodes: Vec<_> = nodes.into_iter().flatten().collect();
+    join_all(nodes.iter().map(|(app, _, _, _)| app.shutdown())).await;
+    for (app, connection, task_exchange, _) in nodes{
+        close_node(app, connection, task_exchange).await;
+    }
+
+    Ok(())
+}
+
+async fn close_node(app: Arc<App>, connection: Arc<Connection>, task_exchange: JoinHandle<()>){
+    connection.close();
+    app.close().await;
+    if timeout(Duration::from_secs(5), task_exchange).await.is_err(){
+        tracing::warn!("exchange task did not finish, quit message may be lost");
+    }
+}
+
+fn serve(app: Arc<App>, connection: Arc<Connection>, node_close: Arc<Notify>) -> PyResult<JoinHandle<()>> {
     let task_exchange;
     {
-        let writer = writer.clone();
+        let connection = connection.clone();
         let app = app.clone();
         task_exchange = tokio::spawn(async move {
             loop{
@@ -72,10 +259,15 @@ async fn main() -> PyResult<()> {
                 let mess = mess.unwrap();
                 match mess{
                     PythonMessage::Request { id, data } => {
-                        write_message(&mut *writer.lock().await, UnixMessage::Request { id, data }).await.unwrap();
+                        connection.send_request(&app.pending, id, data).await;
                     },
+                    PythonMessage::Cancel { id } => connection.forget(id),
                     PythonMessage::Quit => {
-                        write_message(&mut *writer.lock().await, UnixMessage::Quit).await.unwrap();
+                        if let Err(e) = connection.write(UnixMessage::Quit).await{
+                            tracing::warn!(error = %e.description, "unable to send quit");
+                        }
+                        connection.flush().await;
+                        break
                     },
                 }
             }
@@ -84,80 +276,84 @@ async fn main() -> PyResult<()> {
 
     Python::with_gil(|py| -> PyResult<()> {
         let app = app.clone();
-        let close_notify = close_notify.clone();
+        let connection = connection.clone();
+        let node_close = node_close.clone();
         pyo3_asyncio::tokio::future_into_py_with_locals(
             py,
             pyo3_asyncio::tokio::get_current_locals(py)?,
             async move { 
-                loop {
-                    let mess = match read_message(&mut reader).await {
-                        Ok(mess) => mess,
-                        Err(e) => {
-                            println!("error read message {:?}",e);
-                            close_notify.notify_one();
-                            return Ok(())
-                        },
-                    };
-                    match mess {
-                        UnixMessage::Response { id, data } => app.pending.update(id, Ok(data)).await.unwrap(),
-                        UnixMessage::Request { id, data } => {
-                            let writer = writer.clone();
-                            let app = app.clone();
-                            Python::with_gil(|py| -> PyResult<()> {
-                                pyo3_asyncio::tokio::future_into_py_with_locals(
-                                    py,
-                                    pyo3_asyncio::tokio::get_current_locals(py)?,
-                                    async move { 
-                                        if let Err(e) = match app.execute(data).await{
-                                            Ok(data) => write_message(&mut *writer.lock().await, UnixMessage::Response { id, data }).await,
-                                            Err(error) => write_message(&mut *writer.lock().await, UnixMessage::Error { id, error: error.into() }).await,
-                                        }{
-                                            println!("error write response request {:?}",e);
+                while let Some(mut reader) = connection.connect(&app.config).await{
+                    loop {
+                        let mess = match read_message(&mut reader).await {
+                            Ok(mess) => mess,
+                            Err(e) => {
+                                tracing::error!(error = ?e, "error read message");
+                                break
+                            },
+                        };
+                        match mess {
+                            UnixMessage::Response { id, data } => connection.resolve(&app.pending, id, Ok(data)).await,
+                            UnixMessage::Request { id, data } => {
+                                let guard = match app.accept(){
+                                    Some(guard) => guard,
+                                    None => {
+                                        let error = UnicomError::new(UnicomErrorKind::NotAllowed, "node is shutting down");
+                                        if let Err(e) = connection.write(UnixMessage::Error { id, error }).await{
+                                            tracing::error!(error = %e.description, "error write response request");
                                         }
-                                        Ok(())
-                                     }
-                                )?;
-                                Ok(())
-                            }).unwrap();
-                        },
-                        UnixMessage::Quit => return Ok(()),
-                        UnixMessage::Error { id, error } => {
-                            if id == 0{
-                                println!("config error : {:?}", error);
-                                close_notify.notify_one();
-                                return Ok(())
-                            }
-                            app.pending.update(id, Err(error)).await.unwrap()
-                        },
-                    };
+                                        continue
+                                    },
+                                };
+                                let connection = connection.clone();
+                                let app = app.clone();
+                                let method: &str = data.method.clone().into();
+                                let span = tracing::info_span!("request", request_id = id, node = %data.node_name, api = %data.name, method);
+                                Python::with_gil(|py| -> PyResult<()> {
+                                    pyo3_asyncio::tokio::future_into_py_with_locals(
+                                        py,
+                                        pyo3_asyncio::tokio::get_current_locals(py)?,
+                                        async move { 
+                                            let _guard = guard;
+                                            if let Err(e) = match app.execute(id, data).await{
+                                                Ok(data) => {
+                                                    tracing::debug!(size = data.len(), "request done");
+                                                    connection.write(UnixMessage::Response { id, data }).await
+                                                },
+                                                Err(error) => {
+                                                    tracing::warn!(error = %error.error.description, "request failed");
+                                                    connection.write(UnixMessage::Error { id, error: error.into() }).await
+                                                },
+                                            }{
+                                                tracing::error!(error = %e.description, "error write response request");
+                                            }
+                                            Ok(())
+                                         }.instrument(span)
+                                    )?;
+                                    Ok(())
+                                }).unwrap();
+                            },
+                            UnixMessage::Quit => {
+                                tracing::info!("unicom server quit");
+                                break
+                            },
+                            UnixMessage::Error { id, error } => {
+                                if id == 0{
+                                    tracing::error!(error = ?error, "config error");
+                                    connection.close();
+                                    node_close.notify_one();
+                                    break
+                                }
+                                connection.resolve(&app.pending, id, Err(error)).await
+                            },
+                        };
+                    }
+                    connection.disconnect(&app.pending).await;
                 }
+                Ok(())
             }
         )?;
         Ok(())
-    }).unwrap_or_default();
-
-    if app.runnable(){
-        Python::with_gil(|py| -> PyResult<()> {
-            let app = app.clone();
-            let close_notify = close_notify.clone();
-            pyo3_asyncio::tokio::future_into_py_with_locals(
-                py,
-                pyo3_asyncio::tokio::get_current_locals(py)?,
-                async move { 
-                    app.run().await;
-                    close_notify.notify_one();
-                    Ok(())
-                }
-            )?;
-            Ok(())
-        }).unwrap_or_default();
-    }
-
-    close_notify.notified().await;
-    
-    app.close().await;
-    sleep(Duration::from_secs(1)).await;
-    task_exchange.abort();
+    })?;
 
-    Ok(())
-}
\ No newline at end of file
+    Ok(task_exchange)
+}